@@ -1,28 +1,71 @@
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::Debug,
     fs::{self, File},
     io::{Read, Write},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    process::Command,
     time::SystemTime, rc::Rc,
 };
 
-use crate::source;
+use crate::{frontmatter::FrontMatter, logging, markdown, source, BUILD_DIR, CONTENT, PAGE_BUILD_DIR};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
 use std::cell::RefCell;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CacheData {
     pub name: PathBuf,
     pub title: String,
-    pub created: SystemTime,
+    /// When the markdown last changed; merely touching the file does not count.
     pub last_modified: SystemTime,
+    /// What the markdown file looked like when it was cached; `None` in caches of older versions.
+    #[serde(default)]
+    pub source: Option<SourceStamp>,
     pub tags: Vec<String>,
+    /// When the post was published, see `publish_date`.
+    pub date: DateTime<FixedOffset>,
+    /// Whether `date` is only the file's creation time, which is resolved again on every build
+    /// until the post is committed (see `update_fallback_dates`); `None` in caches of older
+    /// versions.
+    #[serde(default)]
+    pub date_from_filesystem: Option<bool>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub layout: Option<String>,
+    pub slug: Option<String>,
+    pub permalink: Option<String>,
+    pub draft: bool,
+    /// Whether the post has code blocks, and so depends on the syntax theme.
+    #[serde(default)]
+    pub code_blocks: bool,
+    /// The plain text of the post's summary (see `markdown::summary_text`), so `index.json` is
+    /// written without reading every post again; `None` in caches of older versions.
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// Every other front matter field, exposed to templates as `page.<field>`.
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+/// Identifies the contents of a source file. The modification time and size are a cheap check;
+/// the hash decides whether a file whose time or size differs really changed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SourceStamp {
+    pub modified: SystemTime,
+    pub size: u64,
+    pub hash: String,
+}
+
+impl SourceStamp {
+    fn new(modified: SystemTime, contents: &[u8]) -> SourceStamp {
+        SourceStamp { modified, size: contents.len() as u64, hash: content_hash(contents) }
+    }
 }
 
 pub struct DataManager {
-    pub source_files: RefCell<HashMap<PathBuf, SystemTime>>,
+    /// The modification time and size of every source file on disk.
+    pub source_files: RefCell<HashMap<PathBuf, (SystemTime, u64)>>,
     pub cache: RefCell<Vec<CacheData>>,
     pub required_changes: RefCell<Vec<PathBuf>>,
     cache_name: Rc<str>,
@@ -30,35 +73,43 @@ pub struct DataManager {
 
 trait ReturnJson {
     type Output;
-    fn populate_struct(&self) -> Self::Output;
+    fn populate_struct(&self) -> Result<Self::Output>;
 }
 
 impl ReturnJson for PathBuf {
     type Output = CacheData;
 
-    fn populate_struct(&self) -> Self::Output {
-        let file_contents = fs::read_to_string(self).unwrap();
-        match source::HeaderParser::get_data(&file_contents) {
-            Some(file_data) => {
-                CacheData {
-                    name: self.into(),
-                    title: file_data.title,
-                    last_modified: modification_time(self.into()),
-                    tags: file_data.tags,
-                    created: created_date(self.into()),
-                }
-            }
-            None => {
-                CacheData {
-                    name: self.into(),
-                    title: "INVALID - NO TITLE".to_string(),
-                    last_modified: modification_time(self.into()),
-                    tags: vec![],
-                    created: created_date(self.into()),
-                }
-            }
-        }
+    fn populate_struct(&self) -> Result<Self::Output> {
+        let file_contents = fs::read_to_string(self)?;
+        let (front_matter, markdown_body) = FrontMatter::parse(self, &file_contents)?;
+        validate_path_overrides(self, &front_matter)?;
+        let title = if front_matter.title.trim().is_empty() {
+            logging::error(format!("`{}`: All files must contain a valid title.", self.display()).as_str());
+            "INVALID - NO TITLE".to_string()
+        } else {
+            front_matter.title
+        };
 
+        let modified = modification_time(self.into());
+        let (date, date_from_filesystem) = publish_date(self, front_matter.date);
+        Ok(CacheData {
+            name: self.into(),
+            title,
+            last_modified: modified,
+            source: Some(SourceStamp::new(modified, file_contents.as_bytes())),
+            tags: front_matter.tags,
+            date,
+            date_from_filesystem: Some(date_from_filesystem),
+            description: front_matter.description,
+            author: front_matter.author,
+            layout: front_matter.layout,
+            slug: front_matter.slug,
+            permalink: front_matter.permalink,
+            draft: front_matter.draft,
+            code_blocks: markdown::has_code_blocks(markdown_body),
+            summary: Some(markdown::summary_text(markdown_body)),
+            extra: front_matter.extra,
+        })
     }
 }
 
@@ -68,19 +119,62 @@ impl DataManager {
         cache.retain(|item| self.source_files.borrow().contains_key(&item.name));
     }
 
-    pub fn update_outdated_entries(&mut self) {
+    /// Re-read the entries whose markdown changed. A file whose modification time or size differs
+    /// from the cache is hashed, so `touch`, `git checkout` or copying the project only rebuilds
+    /// the pages whose contents are actually different.
+    pub fn update_outdated_entries(&mut self) -> Result<()> {
         let mut cache = self.cache.borrow_mut();
         for entry in cache.iter_mut() {
-            if &entry.last_modified != self.source_files.borrow().get(&entry.name).unwrap() {
-                *entry = entry.name.populate_struct();
-                self.required_changes
-                    .borrow_mut()
-                    .push(entry.name.clone());
+            let (modified, size) = self.source_files.borrow()[&entry.name];
+            if let Some(stamp) = &entry.source {
+                if stamp.modified == modified && stamp.size == size {
+                    continue;
+                }
+                if stamp.size == size {
+                    let current = SourceStamp::new(modified, &fs::read(&entry.name)?);
+                    if current.hash == stamp.hash {
+                        entry.source = Some(current);
+                        continue;
+                    }
+                }
             }
+            *entry = entry.name.populate_struct()?;
+            self.required_changes
+                .borrow_mut()
+                .push(entry.name.clone());
         }
+        drop(cache);
+        self.update_fallback_dates()
     }
 
-    pub fn add_new_entries(&mut self) {
+    /// Resolve the dates taken from the filesystem again, so a post built before it was committed
+    /// gets its commit date once it is, just like on a machine without this cache.
+    fn update_fallback_dates(&self) -> Result<()> {
+        let mut cache = self.cache.borrow_mut();
+        let mut required_changes = self.required_changes.borrow_mut();
+        for entry in cache.iter_mut().filter(|entry| entry.date_from_filesystem != Some(false)) {
+            if required_changes.contains(&entry.name) {
+                continue;
+            }
+            let date = match entry.date_from_filesystem {
+                // The markdown is unchanged, so only a commit can have dated it since.
+                Some(_) => git_date(&entry.name),
+                // Older caches do not say where the date came from, so find out once.
+                None => {
+                    let current = entry.name.populate_struct()?;
+                    current.date_from_filesystem.filter(|&fallback| !fallback).map(|_| current.date)
+                }
+            };
+            entry.date_from_filesystem = Some(date.is_none());
+            if let Some(date) = date.filter(|date| *date != entry.date) {
+                entry.date = date;
+                required_changes.push(entry.name.clone());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_new_entries(&mut self) -> Result<()> {
         let cache_set: HashSet<PathBuf> = self
             .cache
             .borrow()
@@ -90,15 +184,28 @@ impl DataManager {
 
         for file in self.source_files.borrow().keys() {
             if !cache_set.contains(file) {
-                self.cache.borrow_mut().push(file.populate_struct());
+                self.cache.borrow_mut().push(file.populate_struct()?);
                 self.required_changes.borrow_mut().push(file.clone());
             }
         }
+        Ok(())
+    }
+
+    /// Mark every source file as requiring a rebuild, regardless of what the cache says.
+    ///
+    /// Used when something every page depends on (e.g. `page.html` or `config.toml`) changes.
+    pub fn mark_all_changed(&self) {
+        let mut required_changes = self.required_changes.borrow_mut();
+        for file in self.source_files.borrow().keys() {
+            if !required_changes.contains(file) {
+                required_changes.push(file.clone());
+            }
+        }
     }
 
     pub fn write_to_json(&self) -> Result<()> {
         let mut buffer = File::create(self.cache_name.as_ref()).unwrap();
-        serde_json::to_writer_pretty(&mut buffer, &self.cache.take()).unwrap();
+        serde_json::to_writer_pretty(&mut buffer, &*self.cache.borrow()).unwrap();
 
         Ok(())
     }
@@ -107,13 +214,32 @@ impl DataManager {
     /// Note: this method does not include the write to file provided by `write_to_json`.
     pub fn process_data(&mut self) -> Result<()> {
         self.remove_missing_entries();
-        self.update_outdated_entries();
-        self.add_new_entries();
+        self.update_outdated_entries()?;
+        self.add_new_entries()?;
 
         Ok(())
     }
 }
 
+/// A `slug` is a single file name and a `permalink` must stay inside `BUILD_DIR`; anything else
+/// would write the page somewhere unexpected.
+fn validate_path_overrides(path: &Path, front_matter: &FrontMatter) -> Result<()> {
+    if let Some(slug) = &front_matter.slug {
+        if slug.trim().is_empty() || slug.contains(['/', '\\']) || slug == "." || slug == ".." {
+            bail!("`{}`: `slug: {}` must be a plain file name without slashes", path.display(), slug);
+        }
+    }
+    if let Some(permalink) = &front_matter.permalink {
+        let escapes = Path::new(permalink.trim_start_matches('/'))
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+        if escapes {
+            bail!("`{}`: `permalink: {}` must be a path inside the site", path.display(), permalink);
+        }
+    }
+    Ok(())
+}
+
 /// Get the time of (since UNIX_EPOCH) of when a given file was last modified in seconds.
 ///
 /// In order for the caching system to work, we need to detect out of date files (i.e. files the
@@ -122,20 +248,68 @@ impl DataManager {
 fn modification_time(path: PathBuf) -> SystemTime {
     let metadata = fs::metadata(&path).unwrap();
 
-    let date_modified = metadata
+    metadata
         .modified()
-        .unwrap();
+        .unwrap()
+}
 
-    date_modified
+/// A 64 bit FNV-1a hash of a file's contents, as hex. Stable across builds and Rust versions,
+/// which is all change detection needs.
+pub fn content_hash(contents: &[u8]) -> String {
+    let hash = contents.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}")
 }
 
-fn created_date(path: PathBuf) -> SystemTime {
-    let metadata = fs::metadata(&path).unwrap();
-    return metadata.created().unwrap();
+/// When a post was published: its `date` header, else a `2024-03-01-` prefix in its file name,
+/// else the date of the commit that added it to git. Filesystem times change on every clone, so
+/// they are only the last resort, flagged by the returned `bool`.
+fn publish_date(path: &Path, date: Option<DateTime<FixedOffset>>) -> (DateTime<FixedOffset>, bool) {
+    match date.or_else(|| file_name_date(path)).or_else(|| git_date(path)) {
+        Some(date) => (date, false),
+        None => (filesystem_date(path), true),
+    }
+}
+
+fn file_name_date(path: &Path) -> Option<DateTime<FixedOffset>> {
+    let stem = path.file_stem()?.to_str()?;
+    let prefix = stem.get(..10)?;
+    if !matches!(stem.as_bytes().get(10), None | Some(b'-' | b'_')) {
+        return None;
+    }
+    let date = NaiveDate::parse_from_str(prefix, "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().fixed_offset())
+}
+
+/// The commit date of the commit that added the file (following renames), if it is tracked by git.
+fn git_date(path: &Path) -> Option<DateTime<FixedOffset>> {
+    let output = Command::new("git")
+        .args(["log", "--follow", "--diff-filter=A", "--format=%cI", "--"])
+        .arg(path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Listed newest first; a file renamed with `--follow` was added more than once.
+    let log = String::from_utf8(output.stdout).ok()?;
+    DateTime::parse_from_rfc3339(log.lines().last()?.trim()).ok()
+}
+
+fn filesystem_date(path: &Path) -> DateTime<FixedOffset> {
+    let time = fs::metadata(path).and_then(|metadata| metadata.created().or_else(|_| metadata.modified()));
+    match time {
+        Ok(time) => DateTime::<Utc>::from(time).fixed_offset(),
+        Err(err) => {
+            logging::warn(format!("could not date `{}` ({err}); using the current time.", path.display()).as_str());
+            Utc::now().fixed_offset()
+        }
+    }
 }
 
 impl CacheData {
-    fn read(cache_file: &str) -> Result<Vec<Self>> {
+    pub fn read(cache_file: &str) -> Result<Vec<Self>> {
         if !Path::new(cache_file).exists() {
             fs::create_dir_all("cache")?;
             let mut f = File::create(cache_file)?;
@@ -145,14 +319,97 @@ impl CacheData {
         let mut buffer = String::new();
         File::open(cache_file)?.read_to_string(&mut buffer)?;
 
-        Ok(serde_json::from_str(&buffer)?)
+        match serde_json::from_str(&buffer) {
+            Ok(cache) => Ok(cache),
+            Err(_) => {
+                // Written by an older version of migi; starting over rebuilds every page.
+                logging::warn(format!("`{cache_file}` is outdated or corrupt and will be rebuilt.").as_str());
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Look up the cached entry of a source file.
+    pub fn find<'a>(cache: &'a [CacheData], name: &Path) -> Option<&'a CacheData> {
+        cache.iter().find(|entry| entry.name == name)
+    }
+
+    /// Whether the post is dated in the future and so held back until then (or `--future`).
+    pub fn is_scheduled(&self) -> bool {
+        self.date > Utc::now()
+    }
+
+    /// The template this entry is rendered with, relative to `templates/`.
+    pub fn layout_template(&self) -> String {
+        match &self.layout {
+            Some(layout) if layout.ends_with(".html") => layout.clone(),
+            Some(layout) => format!("{layout}.html"),
+            None => "page.html".to_string(),
+        }
+    }
+
+    /// Where the page generated from this entry is written. A `permalink` places it anywhere under
+    /// `BUILD_DIR`, a `slug` only renames it; otherwise the path follows the markdown file's.
+    pub fn output_file(&self, pretty_urls: bool) -> PathBuf {
+        match (&self.permalink, &self.slug) {
+            (Some(permalink), _) => {
+                let relative = Path::new(permalink.trim_start_matches('/'));
+                let output = Path::new(BUILD_DIR).join(relative);
+                if permalink.ends_with('/') || relative.as_os_str().is_empty() {
+                    output.join("index.html")
+                } else if relative.extension().is_some() {
+                    output
+                } else if pretty_urls {
+                    output.join("index.html")
+                } else {
+                    output.with_extension("html")
+                }
+            }
+            (None, Some(slug)) => {
+                let renamed = self.name.with_file_name(format!("{slug}.md"));
+                Path::new(PAGE_BUILD_DIR).join(source::html_file_name(&renamed, pretty_urls))
+            }
+            (None, None) => Path::new(PAGE_BUILD_DIR).join(source::html_file_name(&self.name, pretty_urls)),
+        }
+    }
+
+    /// The site-root relative url of the page generated from this entry. Pretty urls (and
+    /// permalinks ending in `/`) point at the page's directory rather than its `index.html`.
+    pub fn url(&self, pretty_urls: bool) -> String {
+        let directory_style = pretty_urls || self.permalink.as_deref().is_some_and(|permalink| permalink.ends_with('/'));
+        source::output_url(&self.output_file(pretty_urls), directory_style)
+    }
+
+    /// The markdown file's path under `content/`, as templates see it in `page.path`.
+    pub fn content_path(&self) -> String {
+        self.name.strip_prefix(CONTENT).unwrap_or(&self.name).to_string_lossy().replace('\\', "/")
+    }
+
+    /// The `page` object templates see: the front matter fields plus generated ones like `url`.
+    pub fn page_variables(&self, pretty_urls: bool) -> serde_json::Value {
+        let mut page: serde_json::Map<String, serde_json::Value> = self.extra.clone().into_iter().collect();
+        page.insert("title".into(), self.title.clone().into());
+        page.insert("tags".into(), self.tags.clone().into());
+        page.insert("url".into(), self.url(pretty_urls).into());
+        page.insert("path".into(), self.content_path().into());
+        page.insert("date".into(), self.date.to_rfc3339().into());
+        page.insert("updated".into(), DateTime::<Utc>::from(self.last_modified).to_rfc3339().into());
+        page.insert("description".into(), self.description.clone().into());
+        page.insert("author".into(), self.author.clone().into());
+        page.insert("layout".into(), self.layout.clone().into());
+        page.insert("slug".into(), self.slug.clone().into());
+        page.insert("permalink".into(), self.permalink.clone().into());
+        page.insert("draft".into(), self.draft.into());
+
+        serde_json::Value::Object(page)
     }
 
     pub fn create_manager(raw_files: Vec<PathBuf>, cache_file: &str) -> Result<DataManager> {
-        let mut source_file_cache_info: HashMap<PathBuf, SystemTime> = HashMap::new();
+        let mut source_file_cache_info: HashMap<PathBuf, (SystemTime, u64)> = HashMap::new();
 
         for file in raw_files {
-            source_file_cache_info.insert(file.clone(), modification_time(file));
+            let size = fs::metadata(&file)?.len();
+            source_file_cache_info.insert(file.clone(), (modification_time(file), size));
         }
 
         let manager = DataManager {
@@ -165,3 +422,100 @@ impl CacheData {
         Ok(manager)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, slug: Option<&str>, permalink: Option<&str>) -> CacheData {
+        CacheData {
+            name: PathBuf::from(name),
+            title: "Post".to_string(),
+            last_modified: SystemTime::UNIX_EPOCH,
+            source: None,
+            tags: Vec::new(),
+            date: DateTime::default(),
+            date_from_filesystem: Some(false),
+            description: None,
+            author: None,
+            layout: None,
+            slug: slug.map(str::to_string),
+            permalink: permalink.map(str::to_string),
+            draft: false,
+            code_blocks: false,
+            summary: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn output_file_and_url() {
+        let cases = [
+            // (markdown file, slug, permalink, pretty urls, output, url)
+            ("content/intro.md", None, None, false, "build/page/intro.html", "page/intro.html"),
+            ("content/intro.md", None, None, true, "build/page/intro/index.html", "page/intro/"),
+            ("content/index.md", None, None, true, "build/page/index.html", "page/"),
+            ("content/2023/intro.md", Some("hello"), None, false, "build/page/2023/hello.html", "page/2023/hello.html"),
+            ("content/2023/intro.md", Some("hello"), None, true, "build/page/2023/hello/index.html", "page/2023/hello/"),
+            ("content/intro.md", None, Some("/about/"), false, "build/about/index.html", "about/"),
+            ("content/intro.md", None, Some("/about"), false, "build/about.html", "about.html"),
+            ("content/intro.md", None, Some("/about"), true, "build/about/index.html", "about/"),
+            ("content/intro.md", None, Some("/about.html"), true, "build/about.html", "about.html"),
+            ("content/intro.md", None, Some("/blog/feed.xml"), false, "build/blog/feed.xml", "blog/feed.xml"),
+            ("content/intro.md", None, Some("/"), false, "build/index.html", ""),
+            ("content/intro.md", None, Some(""), false, "build/index.html", "index.html"),
+            ("content/intro.md", Some("hello"), Some("/about/"), false, "build/about/index.html", "about/"),
+        ];
+        for (name, slug, permalink, pretty_urls, output, url) in cases {
+            let entry = entry(name, slug, permalink);
+            let case = format!("{name} slug={slug:?} permalink={permalink:?} pretty={pretty_urls}");
+            assert_eq!(entry.output_file(pretty_urls), PathBuf::from(output), "{case}");
+            assert_eq!(entry.url(pretty_urls), url, "{case}");
+        }
+    }
+
+    fn validate(slug: Option<&str>, permalink: Option<&str>) -> Result<()> {
+        let front_matter = FrontMatter {
+            slug: slug.map(str::to_string),
+            permalink: permalink.map(str::to_string),
+            ..FrontMatter::default()
+        };
+        validate_path_overrides(Path::new("content/post.md"), &front_matter)
+    }
+
+    #[test]
+    fn accepts_plain_slugs_and_permalinks_inside_the_site() {
+        for (slug, permalink) in [
+            (Some("hello"), None),
+            (Some("hello.world"), None),
+            (None, Some("/about/")),
+            (None, Some("blog/post.html")),
+            (None, Some("./about")),
+            (None, Some("/")),
+        ] {
+            assert!(validate(slug, permalink).is_ok(), "slug={slug:?} permalink={permalink:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_slugs_with_slashes() {
+        for slug in ["../x", "a/b", "a\\b", "..", ".", "", " "] {
+            assert!(validate(Some(slug), None).is_err(), "{slug:?}");
+        }
+        assert_eq!(
+            validate(Some("a/b"), None).unwrap_err().to_string(),
+            "`content/post.md`: `slug: a/b` must be a plain file name without slashes"
+        );
+    }
+
+    #[test]
+    fn rejects_permalinks_leaving_the_site() {
+        for permalink in ["../x", "/../x", "/blog/../../x", "blog/.."] {
+            assert!(validate(None, Some(permalink)).is_err(), "{permalink:?}");
+        }
+        assert_eq!(
+            validate(None, Some("../x")).unwrap_err().to_string(),
+            "`content/post.md`: `permalink: ../x` must be a path inside the site"
+        );
+    }
+}
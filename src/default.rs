@@ -1,10 +1,28 @@
 pub fn get_config() -> String {
     String::from(
-        r#"[themes]
+        r#"# Where the site is published; feeds, the sitemap and absolute urls are only written once set.
+# base_url = "https://example.com"
+title = "My website"
+search = true
+
+[themes]
 syntax = "Tomorrow-Night.tmTheme"
 
 [tagging]
-sorted = true
+sort_by = "date"
+order = "desc"
+item = '<li><a href="{{ root }}{{ page.url }}">{{ page.title | escape }}</a></li>'
+
+[urls]
+pretty = false
+
+[feeds]
+atom = true
+rss = true
+json = true
+
+[sitemap]
+# robots = true   # also write a robots.txt pointing at the sitemap
 "#,
     )
 }
@@ -262,19 +280,21 @@ pub fn get_theme() -> String {
     )
 }
 
-pub fn get_page_template() -> String {
+pub fn get_base_template() -> String {
     String::from(
         r#"<!DOCTYPE html>
 <html lang="en">
     <head>
-        <title></title>
+        <title>{% block title %}{% endblock title %}</title>
         <meta charset="UTF-8">
         <meta name="viewport" content="width=device-width, initial-scale=1">
-        <link href="../assets/css/style.css" rel="stylesheet">
+        <link href="{{ root }}assets/css/style.css" rel="stylesheet">
+        {% if config.feeds %}<link href="{{ root }}atom.xml" rel="alternate" type="application/atom+xml" title="{{ config.title | default(value='') }}">{% endif %}
     </head>
     <body>
+        {% include "partials/nav.html" %}
         <div class="wrapper">
-			{{ content }}
+            {% block content %}{% endblock content %}
         </div>
     </body>
 </html>
@@ -282,26 +302,215 @@ pub fn get_page_template() -> String {
     )
 }
 
+pub fn get_nav_partial() -> String {
+    String::from(
+        r#"<nav class="wrapper">
+    {% if config.urls.pretty %}
+    <a href="./{{ root }}">Home</a>
+    <a href="{{ root }}tags/">Tags</a>
+    {% else %}
+    <a href="{{ root }}index.html">Home</a>
+    <a href="{{ root }}tags/index.html">Tags</a>
+    {% endif %}
+    {% if config.search %}
+    <input type="search" placeholder="Search" data-search>
+    <ul data-search-results></ul>
+    <script src="{{ root }}search.js" defer></script>
+    {% endif %}
+</nav>
+"#,
+    )
+}
+
+pub fn get_page_template() -> String {
+    String::from(
+        r#"{% extends "base.html" %}
+
+{% block title %}{{ page.title | escape }}{% endblock title %}
+
+{% block content %}
+{{ content | safe }}
+{% endblock content %}
+"#,
+    )
+}
+
 pub fn get_index_template() -> String {
     String::from(
-        r#"<!DOCTYPE html>
-<html lang="en">
-    <head>
-        <title>Index</title>
-        <meta charset="UTF-8">
-        <meta name="viewport" content="width=device-width, initial-scale=1">
-        <link href="assets/css/style.css" rel="stylesheet">
-    </head>
-    <body>
-        <div class="wrapper">
-            <h1>Welcome to your website</h1>
-            <p>This is the default homepage generated for you.</p>
-            <p>You can customize it to your liking! Look at your first page:</p>
+        r#"{% extends "base.html" %}
 
-			{{ generic_post }}
-        </div>
-    </body>
-</html>
+{% block title %}Index{% endblock title %}
+
+{% block content %}
+<h1>Welcome to your website</h1>
+<p>This is the default homepage generated for you.</p>
+<p>You can customize it to your liking! Look at your first page:</p>
+
+<ul>
+{% for post in tags.generic_post %}
+    <li>
+        <a href="{{ root }}{{ post.url }}">{{ post.title | escape }}</a>
+        {% if post.date %}<time>{{ post.date | date(format="%Y-%m-%d") }}</time>{% endif %}
+    </li>
+{% endfor %}
+</ul>
+{% endblock content %}
+"#,
+    )
+}
+
+pub fn get_tag_template() -> String {
+    String::from(
+        r#"{% extends "base.html" %}
+
+{% block title %}Tagged {{ term.name | escape }}{% endblock title %}
+
+{% block content %}
+<h1>Tagged {{ term.name | escape }}</h1>
+
+<ul>
+{% for post in paginator.pages %}
+    <li><a href="{{ root }}{{ post.url }}">{{ post.title | escape }}</a></li>
+{% endfor %}
+</ul>
+
+{% if paginator.previous %}<a href="{{ root }}{{ paginator.previous }}">Newer</a>{% endif %}
+{% if paginator.next %}<a href="{{ root }}{{ paginator.next }}">Older</a>{% endif %}
+{% endblock content %}
+"#,
+    )
+}
+
+pub fn get_tags_template() -> String {
+    String::from(
+        r#"{% extends "base.html" %}
+
+{% block title %}Tags{% endblock title %}
+
+{% block content %}
+<h1>Tags</h1>
+
+<ul>
+{% for term in terms %}
+    <li><a href="{{ root }}{{ term.url }}">{{ term.name | escape }}</a> ({{ term.count }})</li>
+{% endfor %}
+</ul>
+{% endblock content %}
+"#,
+    )
+}
+
+pub fn get_search_script() -> String {
+    String::from(
+        r#"// Search box for a migi site, written by `migi build` when `search = true`.
+// Include it with <script src="{{ root }}search.js" defer></script> and mark up
+// <input type="search" data-search> and <ul data-search-results></ul>.
+(function () {
+    var root = new URL(".", document.currentScript.src).href;
+    var index = null;
+
+    // Filled in from migi's src/search.rs, whose `tokenize` and `stem` these must match.
+    var SUFFIXES = __SUFFIXES__;
+    var KEEP_DOUBLED = __KEEP_DOUBLED__;
+
+    function length(text) {
+        return Array.from(text).length;
+    }
+
+    function undouble(base) {
+        var chars = Array.from(base);
+        var last = chars[chars.length - 1];
+        if (chars.length >= 2 && chars[chars.length - 2] === last && KEEP_DOUBLED.indexOf(last) < 0) {
+            return chars.slice(0, -1).join("");
+        }
+        return base;
+    }
+
+    function stem(word) {
+        for (var i = 0; i < SUFFIXES.length; i++) {
+            var suffix = SUFFIXES[i][0];
+            if (word.endsWith(suffix)) {
+                var base = word.slice(0, -suffix.length);
+                if (length(base) < 3) {
+                    continue;
+                }
+                return undouble(base) + SUFFIXES[i][1];
+            }
+        }
+        if (word.endsWith("s")) {
+            var base = word.slice(0, -1);
+            if (length(base) >= 3 && !/[sui]$/.test(base)) {
+                return base;
+            }
+        }
+        return word;
+    }
+
+    function tokenize(text) {
+        return text.split(/[^\p{Alphabetic}\p{N}]+/u)
+            .filter(function (word) { return length(word) > 1; })
+            .map(function (word) { return stem(word.toLowerCase()); });
+    }
+
+    // Posts containing every query term, best first. Terms match as prefixes, so results show
+    // up while a word is still being typed.
+    function search(query) {
+        var tokens = tokenize(query);
+        var scores = {};
+        tokens.forEach(function (token, position) {
+            var found = {};
+            Object.keys(index.terms).forEach(function (term) {
+                if (!term.startsWith(token)) {
+                    return;
+                }
+                var postings = index.terms[term];
+                for (var i = 0; i < postings.length; i += 2) {
+                    found[postings[i]] = (found[postings[i]] || 0) + postings[i + 1];
+                }
+            });
+            if (position === 0) {
+                scores = found;
+                return;
+            }
+            Object.keys(scores).forEach(function (page) {
+                if (page in found) {
+                    scores[page] += found[page];
+                } else {
+                    delete scores[page];
+                }
+            });
+        });
+        return Object.keys(scores)
+            .sort(function (a, b) { return scores[b] - scores[a]; })
+            .slice(0, 10)
+            .map(function (page) { return index.pages[page]; });
+    }
+
+    function show(results, pages) {
+        results.replaceChildren.apply(results, pages.map(function (page) {
+            var item = document.createElement("li");
+            var link = document.createElement("a");
+            link.href = root + page.url;
+            link.textContent = page.title;
+            var snippet = document.createElement("p");
+            snippet.textContent = page.snippet;
+            item.append(link, snippet);
+            return item;
+        }));
+    }
+
+    document.querySelectorAll("[data-search]").forEach(function (input) {
+        var results = document.querySelector("[data-search-results]");
+        input.addEventListener("input", function () {
+            var load = index ? Promise.resolve() : fetch(root + "search-index.json")
+                .then(function (response) { return response.json(); })
+                .then(function (loaded) { index = loaded; });
+            load.then(function () {
+                show(results, input.value.trim() ? search(input.value) : []);
+            });
+        });
+    });
+})();
 "#,
     )
 }
@@ -332,7 +541,9 @@ It is written in markdown.
 
 ### Further Customization
 
-You can modify the `page.html` template inside the `templates/` directory if you wish to add things to all pages like a header, navbar, footer, etc.
+You can modify the `base.html` template inside the `templates/` directory if you wish to add things to all pages like a header, navbar, footer, etc. The navbar itself lives in `templates/partials/nav.html`.
+
+Templates use the [Tera](https://keats.github.io/tera/docs/) template language: `page.html` sees this post as `page` and its html as `content`, and every template can loop over `pages` or `tags.<name>`.
 "#,
 )
     }
@@ -0,0 +1,51 @@
+use crate::{cache::CacheData, DEPENDENCIES};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// What a post's page was rendered from besides its markdown, e.g. `template:page.html`,
+/// `config:themes` or `asset:assets/syntax/Tomorrow-Night.tmTheme`, each with its content hash.
+pub type PageDependencies = BTreeMap<String, String>;
+
+/// The dependencies every post's page was last rendered with, by markdown file. A page is
+/// re-rendered once any of them hashes differently, so editing `page.html` rebuilds the posts
+/// using it but not those with another layout.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Dependencies(BTreeMap<PathBuf, PageDependencies>);
+
+impl Dependencies {
+    /// The recorded dependencies; empty (so everything is rendered) when there are none yet.
+    pub fn read() -> Dependencies {
+        fs::read_to_string(DEPENDENCIES)
+            .ok()
+            .and_then(|dependencies| serde_json::from_str(&dependencies).ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether a post's page was rendered from something other than `current`, or never at all.
+    pub fn is_outdated(&self, name: &Path, current: &PageDependencies) -> bool {
+        self.0.get(name) != Some(current)
+    }
+
+    pub fn record(&mut self, name: &Path, current: PageDependencies) {
+        self.0.insert(name.to_path_buf(), current);
+    }
+
+    pub fn remove(&mut self, name: &Path) {
+        self.0.remove(name);
+    }
+
+    /// Write the dependencies of the posts still in `cache`.
+    pub fn write(mut self, cache: &[CacheData]) -> Result<()> {
+        self.0.retain(|name, _| CacheData::find(cache, name).is_some());
+        if let Some(parent) = Path::new(DEPENDENCIES).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(DEPENDENCIES, serde_json::to_string_pretty(&self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use crate::{
+    cache::CacheData,
+    frontmatter::{parse_date, terms_from_value, FrontMatter},
+    markdown::{self, Config, Feeds},
+    source, BUILD_DIR, CONTENT,
+};
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tera::Value;
+
+/// Writes the site's feeds: `atom.xml`, `rss.xml` and `feed.json` at the root, and the same set
+/// next to each tag page (`tags/<tag>/atom.xml`).
+pub struct FeedWriter<'a> {
+    config: &'a Config,
+    settings: &'a Feeds,
+    base_url: &'a str,
+    contents: &'a Contents,
+}
+
+/// Compiled post html by `page.path`, so posts in several feeds are only compiled once, and the
+/// summaries cached with the posts.
+pub struct Contents {
+    compiled: RefCell<HashMap<String, String>>,
+    summaries: HashMap<String, String>,
+}
+
+impl Contents {
+    pub fn new(cache: &[CacheData]) -> Contents {
+        let summaries = cache
+            .iter()
+            .filter_map(|entry| Some((entry.content_path(), entry.summary.clone()?)))
+            .collect();
+        Contents { compiled: RefCell::default(), summaries }
+    }
+
+    pub fn html(&self, page: &Value) -> Result<String> {
+        let path = page["path"].as_str().unwrap_or_default();
+        if let Some(content) = self.compiled.borrow().get(path) {
+            return Ok(content.clone());
+        }
+        let content = markdown::compile(&markdown_body(page)?);
+        self.compiled.borrow_mut().insert(path.to_string(), content.clone());
+        Ok(content)
+    }
+
+    /// A post's text without markup.
+    pub fn text(&self, page: &Value) -> Result<String> {
+        Ok(markdown::plain_text(&markdown_body(page)?))
+    }
+
+    /// A post's summary without markup: its `description` as written, else the text of what
+    /// `summary` would take from its html.
+    pub fn summary_text(&self, page: &Value) -> Result<String> {
+        if let Some(description) = page["description"].as_str().filter(|description| !description.trim().is_empty()) {
+            return Ok(description.to_string());
+        }
+        match self.summaries.get(page["path"].as_str().unwrap_or_default()) {
+            Some(summary) => Ok(summary.clone()),
+            None => Ok(markdown::summary_text(&markdown_body(page)?)),
+        }
+    }
+}
+
+/// A post's markdown without its front matter.
+fn markdown_body(page: &Value) -> Result<String> {
+    let source = Path::new(CONTENT).join(page["path"].as_str().unwrap_or_default());
+    let file_contents = fs::read_to_string(&source)?;
+    let (_, markdown_body) = FrontMatter::parse(&source, &file_contents)?;
+    Ok(markdown_body.to_string())
+}
+
+/// One post of a feed, with everything resolved to absolute urls and parsed dates.
+pub struct FeedEntry {
+    pub title: String,
+    pub url: String,
+    pub date: DateTime<FixedOffset>,
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+    /// Html, for Atom and RSS.
+    pub summary: String,
+    /// Plain text, for JSON Feed.
+    pub summary_text: String,
+    pub content: String,
+}
+
+impl<'a> FeedWriter<'a> {
+    /// `None` when there is no `[feeds]` section or no `base_url`: feeds need absolute urls.
+    pub fn new(config: &'a Config, contents: &'a Contents) -> Option<FeedWriter<'a>> {
+        let settings = config.feeds.as_ref()?;
+        let base_url = config.base_url.as_deref()?.trim_end_matches('/');
+        Some(FeedWriter { config, settings, base_url, contents })
+    }
+
+    pub fn settings(&self) -> &Feeds {
+        self.settings
+    }
+
+    /// The absolute url of a site-root relative one.
+    pub fn absolute_url(&self, url: &str) -> String {
+        format!("{}/{}", self.base_url, url.trim_start_matches('/'))
+    }
+
+    /// Write the enabled feed formats for `pages` into `directory` (relative to `BUILD_DIR`),
+    /// returning the files written.
+    pub fn write(&self, directory: &Path, title: &str, pages: &[Value]) -> Result<Vec<PathBuf>> {
+        let entries = self.entries(pages)?;
+        let directory = Path::new(BUILD_DIR).join(directory);
+        fs::create_dir_all(&directory)?;
+
+        let mut written = Vec::new();
+        if self.settings().atom {
+            let output = directory.join("atom.xml");
+            fs::write(&output, self.atom(&output, title, &entries))?;
+            written.push(output);
+        }
+        if self.settings().rss {
+            let output = directory.join("rss.xml");
+            fs::write(&output, self.rss(&output, title, &entries))?;
+            written.push(output);
+        }
+        if self.settings().json {
+            let output = directory.join("feed.json");
+            fs::write(&output, serde_json::to_string_pretty(&self.json_feed(&output, title, &entries))?)?;
+            written.push(output);
+        }
+        Ok(written)
+    }
+
+    /// The newest `limit` posts, newest first.
+    pub fn entries(&self, pages: &[Value]) -> Result<Vec<FeedEntry>> {
+        let mut dated: Vec<(DateTime<FixedOffset>, &Value)> = pages
+            .iter()
+            .filter_map(|page| Some((parse_date(page["date"].as_str()?)?, page)))
+            .collect();
+        dated.sort_by_key(|(date, _)| Reverse(*date));
+        dated.truncate(self.settings().limit);
+
+        let mut entries = Vec::new();
+        for (date, page) in dated {
+            let content = self.contents.html(page)?;
+            entries.push(FeedEntry {
+                title: page["title"].as_str().unwrap_or_default().to_string(),
+                url: self.absolute_url(page["url"].as_str().unwrap_or_default()),
+                date,
+                author: page["author"].as_str().map(str::to_string),
+                tags: terms_from_value(&page["tags"]),
+                summary: summary(page, &content),
+                summary_text: self.contents.summary_text(page)?,
+                content,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn atom(&self, output: &Path, title: &str, entries: &[FeedEntry]) -> String {
+        let updated = entries.first().map(|entry| entry.date).unwrap_or_else(|| Utc::now().fixed_offset());
+        let mut feed = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        feed += &format!("<title>{}</title>\n", escape(title));
+        feed += &format!("<link href=\"{}\" rel=\"self\" type=\"application/atom+xml\"/>\n", escape(&self.output_url(output)));
+        feed += &format!("<link href=\"{}/\"/>\n", escape(self.base_url));
+        feed += &format!("<id>{}</id>\n", escape(&self.output_url(output)));
+        feed += &format!("<updated>{}</updated>\n", updated.to_rfc3339());
+        // Atom requires an author on every entry, which entries without their own inherit from here.
+        feed += &format!("<author><name>{}</name></author>\n", escape(self.author(title)));
+        for entry in entries {
+            feed += &format!("<entry xml:base=\"{}\">\n", escape(&entry.url));
+            feed += &format!("<title>{}</title>\n", escape(&entry.title));
+            feed += &format!("<link href=\"{}\" rel=\"alternate\" type=\"text/html\"/>\n", escape(&entry.url));
+            feed += &format!("<id>{}</id>\n", escape(&entry.url));
+            feed += &format!("<published>{0}</published>\n<updated>{0}</updated>\n", entry.date.to_rfc3339());
+            if let Some(author) = &entry.author {
+                feed += &format!("<author><name>{}</name></author>\n", escape(author));
+            }
+            feed += &format!("<summary type=\"html\">{}</summary>\n", escape(&entry.summary));
+            feed += &format!("<content type=\"html\">{}</content>\n", escape(&entry.content));
+            feed += "</entry>\n";
+        }
+        feed + "</feed>\n"
+    }
+
+    fn rss(&self, output: &Path, title: &str, entries: &[FeedEntry]) -> String {
+        let updated = entries.first().map(|entry| entry.date).unwrap_or_else(|| Utc::now().fixed_offset());
+        let mut feed = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:content=\"http://purl.org/rss/1.0/modules/content/\">\n<channel>\n",
+        );
+        feed += &format!("<title>{}</title>\n", escape(title));
+        feed += &format!("<link>{}/</link>\n", escape(self.base_url));
+        feed += &format!("<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n", escape(&self.output_url(output)));
+        feed += &format!("<description>{}</description>\n", escape(self.config.description.as_deref().unwrap_or(title)));
+        feed += &format!("<lastBuildDate>{}</lastBuildDate>\n", updated.to_rfc2822());
+        for entry in entries {
+            feed += "<item>\n";
+            feed += &format!("<title>{}</title>\n", escape(&entry.title));
+            feed += &format!("<link>{}</link>\n", escape(&entry.url));
+            feed += &format!("<guid isPermaLink=\"true\">{}</guid>\n", escape(&entry.url));
+            feed += &format!("<pubDate>{}</pubDate>\n", entry.date.to_rfc2822());
+            if let Some(author) = &entry.author {
+                feed += &format!("<dc:creator xmlns:dc=\"http://purl.org/dc/elements/1.1/\">{}</dc:creator>\n", escape(author));
+            }
+            feed += &format!("<description>{}</description>\n", escape(&entry.summary));
+            feed += &format!("<content:encoded>{}</content:encoded>\n", escape(&entry.content));
+            feed += "</item>\n";
+        }
+        feed + "</channel>\n</rss>\n"
+    }
+
+    /// A [JSON Feed](https://jsonfeed.org/version/1.1).
+    fn json_feed(&self, output: &Path, title: &str, entries: &[FeedEntry]) -> Value {
+        let items: Vec<Value> = entries
+            .iter()
+            .map(|entry| {
+                let mut item = serde_json::json!({
+                    "id": entry.url,
+                    "url": entry.url,
+                    "title": entry.title,
+                    "content_html": entry.content,
+                    "summary": entry.summary_text,
+                    "date_published": entry.date.to_rfc3339(),
+                    "tags": entry.tags,
+                });
+                if let Some(author) = &entry.author {
+                    item["authors"] = serde_json::json!([{ "name": author }]);
+                }
+                item
+            })
+            .collect();
+
+        let mut feed = serde_json::json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": title,
+            "home_page_url": format!("{}/", self.base_url),
+            "feed_url": self.output_url(output),
+            "authors": [{ "name": self.author(title) }],
+            "items": items,
+        });
+        if let Some(description) = &self.config.description {
+            feed["description"] = description.clone().into();
+        }
+        feed
+    }
+
+    /// The site's `author`, else its `title`, else the title of the feed.
+    fn author<'t>(&'t self, title: &'t str) -> &'t str {
+        self.config.author.as_deref().or(self.config.title.as_deref()).unwrap_or(title)
+    }
+
+    fn output_url(&self, output: &Path) -> String {
+        self.absolute_url(&source::output_url(output, false))
+    }
+}
+
+/// Write `index.json`: every published post with its `page` fields and summary, for tools that
+/// want the site's content without scraping html. Urls are made absolute when `base_url` is set.
+pub fn write_site_index(config: &Config, contents: &Contents, pages: &[Value]) -> Result<PathBuf> {
+    let base_url = config.base_url.as_deref().map(|base_url| base_url.trim_end_matches('/'));
+    let mut entries = Vec::new();
+    for page in pages {
+        let mut entry = page.clone();
+        entry["summary"] = contents.summary_text(page)?.into();
+        if let (Some(base_url), Some(url)) = (base_url, page["url"].as_str()) {
+            entry["absolute_url"] = format!("{base_url}/{url}").into();
+        }
+        entries.push(entry);
+    }
+
+    let index = serde_json::json!({
+        "title": config.title,
+        "base_url": config.base_url,
+        "pages": entries,
+    });
+    let output = Path::new(BUILD_DIR).join("index.json");
+    fs::write(&output, serde_json::to_string_pretty(&index)?)?;
+    Ok(output)
+}
+
+/// A post's summary: its `description`, else the html before a `<!-- more -->` marker, else its
+/// first paragraph.
+pub fn summary(page: &Value, content: &str) -> String {
+    if let Some(description) = page["description"].as_str().filter(|description| !description.trim().is_empty()) {
+        return escape(description);
+    }
+    if let Some((before, _)) = content.split_once("<!-- more -->") {
+        return before.trim().to_string();
+    }
+    match (content.find("<p>"), content.find("</p>")) {
+        (Some(start), Some(end)) if start < end => content[start..end + "</p>".len()].to_string(),
+        _ => String::new(),
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,288 @@
+use crate::logging;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Deserializer};
+use std::{collections::BTreeMap, path::Path};
+use {once_cell::sync::Lazy, regex::Regex};
+
+/// The metadata block at the top of a post: YAML between `---` lines or TOML between `+++` lines.
+///
+/// Known fields are typed; anything else ends up in `extra` untouched.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct FrontMatter {
+    pub title: String,
+    #[serde(deserialize_with = "deserialize_terms")]
+    pub tags: Vec<String>,
+    #[serde(deserialize_with = "deserialize_date")]
+    pub date: Option<DateTime<FixedOffset>>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    /// Render with `templates/<layout>.html` instead of `page.html`.
+    pub layout: Option<String>,
+    /// Replaces the file name in the post's output path and url, keeping its directory.
+    pub slug: Option<String>,
+    /// The full output path of the post relative to the site root, e.g. `/blog/hello/`.
+    pub permalink: Option<String>,
+    /// Drafts are only published by `migi serve` and `migi build --drafts`.
+    pub draft: bool,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Yaml,
+    Toml,
+}
+
+struct RawHeader<'a> {
+    format: Format,
+    header: &'a str,
+    body: &'a str,
+    /// The line number (1-based) of the first line inside the delimiters.
+    first_line: usize,
+}
+
+impl FrontMatter {
+    /// Split a post into its parsed front matter and the markdown body that follows it.
+    ///
+    /// Posts without front matter get the default (empty) metadata and a warning; malformed front
+    /// matter is an error pointing at the offending line of the file.
+    pub fn parse<'a>(path: &Path, text: &'a str) -> Result<(FrontMatter, &'a str)> {
+        let Some(raw) = split(path, text)? else {
+            logging::warn(format!("`{}` does not contain a valid header", path.display()).as_str());
+            return Ok((FrontMatter::default(), text));
+        };
+        if raw.header.trim().is_empty() {
+            return Ok((FrontMatter::default(), raw.body));
+        }
+
+        let front_matter = match raw.format {
+            Format::Yaml => serde_yaml::from_str(raw.header).map_err(|err| {
+                let line = err.location().map(|location| raw.first_line + location.line() - 1);
+                // serde_yaml reports positions relative to the header, so shift them to file lines.
+                static POSITION: Lazy<Regex> = Lazy::new(|| Regex::new(r"line (\d+) column").unwrap());
+                let message = err.to_string();
+                let message = POSITION.replace_all(&message, |caps: &regex::Captures| {
+                    let header_line: usize = caps[1].parse().unwrap_or(1);
+                    format!("line {} column", raw.first_line + header_line - 1)
+                });
+                header_error(path, line, &message)
+            })?,
+            Format::Toml => {
+                let mut table: toml::Table = toml::from_str(raw.header).map_err(|err| {
+                    let line = err
+                        .span()
+                        .map(|span| raw.first_line + raw.header[..span.start].matches('\n').count());
+                    header_error(path, line, err.message())
+                })?;
+                for (_, value) in table.iter_mut() {
+                    stringify_toml_datetimes(value);
+                }
+                FrontMatter::deserialize(toml::Value::Table(table.clone())).map_err(|err| {
+                    // The parsed values no longer carry spans, so point at the first field that
+                    // fails on its own instead.
+                    let line = table
+                        .iter()
+                        .find(|(key, value)| {
+                            let field = toml::Table::from_iter([((*key).clone(), (*value).clone())]);
+                            FrontMatter::deserialize(toml::Value::Table(field)).is_err()
+                        })
+                        .and_then(|(key, _)| toml_key_line(raw.header, key))
+                        .map(|line| raw.first_line + line);
+                    header_error(path, line, err.message())
+                })?
+            }
+        };
+        Ok((front_matter, raw.body))
+    }
+}
+
+/// The line (0-based, within the header) where a top-level TOML key is set.
+fn toml_key_line(header: &str, key: &str) -> Option<usize> {
+    header.lines().position(|line| {
+        let line = line.trim_start();
+        [key.to_string(), format!("\"{key}\""), format!("'{key}'")]
+            .iter()
+            .any(|name| line.strip_prefix(name.as_str()).is_some_and(|rest| rest.trim_start().starts_with('=')))
+    })
+}
+
+fn header_error(path: &Path, line: Option<usize>, message: &str) -> anyhow::Error {
+    let message = message.trim_end();
+    match line {
+        Some(line) => anyhow!("{}:{}: invalid front matter: {}", path.display(), line, message),
+        None => anyhow!("{}: invalid front matter: {}", path.display(), message),
+    }
+}
+
+/// Find the delimited header at the start of a post. Only blank lines may precede it, so a `---`
+/// horizontal rule further down the markdown is never mistaken for front matter.
+fn split<'a>(path: &Path, text: &'a str) -> Result<Option<RawHeader<'a>>> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut offset = 0;
+    let mut opening = None;
+
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        let trimmed = line.trim_end();
+        offset += line.len();
+        match opening {
+            None if trimmed.is_empty() => continue,
+            None => {
+                let (format, delimiter) = match trimmed {
+                    "---" => (Format::Yaml, "---"),
+                    "+++" => (Format::Toml, "+++"),
+                    _ => return Ok(None),
+                };
+                opening = Some((format, delimiter, index + 1, offset));
+            }
+            Some((format, delimiter, opening_line, header_start)) => {
+                if trimmed == delimiter {
+                    return Ok(Some(RawHeader {
+                        format,
+                        header: &text[header_start..offset - line.len()],
+                        body: &text[offset..],
+                        first_line: opening_line + 1,
+                    }));
+                }
+            }
+        }
+    }
+
+    match opening {
+        Some((_, delimiter, opening_line, _)) => Err(anyhow!(
+            "{}:{}: front matter opened with `{}` is never closed",
+            path.display(),
+            opening_line,
+            delimiter
+        )),
+        None => Ok(None),
+    }
+}
+
+/// TOML dates deserialize as a special table, so turn them into the strings `parse_date` expects.
+fn stringify_toml_datetimes(value: &mut toml::Value) {
+    match value {
+        toml::Value::Datetime(datetime) => *value = toml::Value::String(datetime.to_string()),
+        toml::Value::Array(values) => values.iter_mut().for_each(stringify_toml_datetimes),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, value)| stringify_toml_datetimes(value)),
+        _ => {}
+    }
+}
+
+/// Accept either a list (`tags: [a, b]`) or a single whitespace separated string (`tags: a b`).
+fn deserialize_terms<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(terms_from_value(&serde_json::Value::deserialize(deserializer)?))
+}
+
+pub fn terms_from_value(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::Null => vec![],
+        serde_json::Value::String(terms) => terms.split_whitespace().map(str::to_string).collect(),
+        serde_json::Value::Array(terms) => terms
+            .iter()
+            .filter_map(|term| match term {
+                serde_json::Value::String(term) => Some(term.trim().to_string()),
+                serde_json::Value::Null => None,
+                other => Some(other.to_string()),
+            })
+            .filter(|term| !term.is_empty())
+            .collect(),
+        other => vec![other.to_string()],
+    }
+}
+
+fn deserialize_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<FixedOffset>>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(date) => parse_date(&date)
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("`{date}` is not a date (expected e.g. 2024-03-01 or 2024-03-01T09:30:00+02:00)"))),
+        None => Ok(None),
+    }
+}
+
+/// Parse the date formats accepted in front matter. Dates without an offset are taken as UTC.
+pub fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+    if let Ok(parsed) = DateTime::parse_from_rfc3339(date) {
+        return Some(parsed);
+    }
+    if let Ok(parsed) = DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S%:z") {
+        return Some(parsed);
+    }
+    let naive = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))?;
+
+    Some(naive.and_utc().fixed_offset())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<(FrontMatter, &str)> {
+        FrontMatter::parse(Path::new("content/post.md"), text)
+    }
+
+    #[test]
+    fn skips_byte_order_mark_and_leading_blank_lines() {
+        let (front_matter, body) = parse("\u{feff}\n\n---\ntitle: Hello\n---\nBody\n").unwrap();
+        assert_eq!(front_matter.title, "Hello");
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn reads_toml_headers() {
+        let (front_matter, body) = parse("+++\ntitle = \"Hello\"\ntags = [\"rust\", \"wasm\"]\ndate = 2024-03-01\n+++\nBody").unwrap();
+        assert_eq!(front_matter.title, "Hello");
+        assert_eq!(front_matter.tags, ["rust", "wasm"]);
+        assert_eq!(front_matter.date, parse_date("2024-03-01"));
+        assert_eq!(body, "Body");
+    }
+
+    #[test]
+    fn unclosed_header_is_an_error_at_its_opening_line() {
+        let err = parse("\n---\ntitle: Hello\nBody\n").unwrap_err().to_string();
+        assert_eq!(err, "content/post.md:2: front matter opened with `---` is never closed");
+    }
+
+    #[test]
+    fn horizontal_rule_in_the_body_is_not_a_header() {
+        let text = "Intro\n\n---\n\nMore\n";
+        let (front_matter, body) = parse(text).unwrap();
+        assert_eq!(front_matter.title, "");
+        assert_eq!(body, text);
+
+        let (front_matter, body) = parse("---\ntitle: Hello\n---\nIntro\n\n---\n\nMore\n").unwrap();
+        assert_eq!(front_matter.title, "Hello");
+        assert_eq!(body, "Intro\n\n---\n\nMore\n");
+    }
+
+    #[test]
+    fn yaml_errors_point_at_file_lines() {
+        let err = parse("\n---\ntitle: Hello\ntags: [rust\n---\n").unwrap_err().to_string();
+        assert!(err.starts_with("content/post.md:5: invalid front matter: "), "{err}");
+        assert!(!err.contains("line 3 column"), "{err}");
+    }
+
+    #[test]
+    fn yaml_type_errors_point_at_the_field() {
+        let err = parse("---\ntitle: Hello\ndraft: maybe\n---\n").unwrap_err().to_string();
+        assert!(err.starts_with("content/post.md:3: invalid front matter: "), "{err}");
+    }
+
+    #[test]
+    fn toml_syntax_errors_point_at_file_lines() {
+        let err = parse("+++\ntitle = \"Hello\"\ntags = [\n+++\n").unwrap_err().to_string();
+        assert!(err.starts_with("content/post.md:4: invalid front matter: "), "{err}");
+    }
+
+    #[test]
+    fn toml_type_errors_point_at_the_field() {
+        let err = parse("+++\ntitle = \"Hello\"\ndraft = \"yes\"\n+++\n").unwrap_err().to_string();
+        assert!(err.starts_with("content/post.md:3: invalid front matter: "), "{err}");
+    }
+}
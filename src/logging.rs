@@ -12,8 +12,7 @@ fn writer(message: &str, prompt: &str, color: ColorSpec) -> Result<()> {
 }
 
 fn create_color_spec(color: Color) -> ColorSpec {
-    let colorspec = ColorSpec::new().set_fg(Some(color)).to_owned();
-    return colorspec;
+    ColorSpec::new().set_fg(Some(color)).to_owned()
 }
 
 pub fn info(message: &str) {
@@ -1,21 +1,34 @@
 use std::env;
 use anyhow::Result;
-use walkdir::WalkDir;
+use source::{BuildOptions, RenderMode};
 
 mod cache;
 mod default;
+mod dependencies;
+mod feeds;
+mod frontmatter;
 mod markdown;
+mod outputs;
+mod query;
+mod search;
+mod sitemap;
 mod source;
 mod templates;
 mod logging;
+mod server;
+mod watch;
 
 pub const BUILD_DIR: &str = "build/";
 pub const PAGE_BUILD_DIR: &str = "build/page/";
 pub const TEMPLATES_DIR: &str = "templates/";
 pub const PAGE_TEMPLATE: &str = "templates/page.html";
 pub const CONTENT_CACHE: &str = "cache/content.json";
+pub const BUILD_STAMP: &str = "cache/build.json";
+pub const DEPENDENCIES: &str = "cache/dependencies.json";
+pub const OUTPUTS: &str = "cache/outputs.json";
 pub const CONTENT: &str = "content/";
 pub const ASSETS: &str = "assets/";
+pub const CONFIG: &str = "config.toml";
 
 fn usage(program: &str) {
     eprintln!(
@@ -24,10 +37,34 @@ An extremely minimal static site generator.
 
 Commands:
     new <directory>     create new project directory
-    build               build project outputting html
+    build [--watch] [--drafts] [--future]
+                        build project outputting html, optionally rebuilding on changes
+                        and publishing drafts or posts dated in the future
+    serve [--port <n>]  build project (drafts and future posts included) and serve it on
+                        localhost, rebuilding on changes
     version             show the version of migi\n");
 }
 
+fn build(options: BuildOptions) -> Result<()> {
+    source::prechecks()?;
+    let content_cache = source::scan_cache()?;
+    if source::build_stamp_changed(options)? {
+        content_cache.mark_all_changed();
+    }
+    let work_count = source::markdown_to_html_export(content_cache, options)?;
+    templates::render_templates(TEMPLATES_DIR, options)?;
+    source::copy_assets(ASSETS)?;
+    match work_count {
+        0 => {
+            logging::info("All files are already up to date.");
+        }
+        _ => {
+            logging::info(format!("{} files were affected.", work_count).as_str());
+        }
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let mut arguments = env::args();
     let program = arguments.next().unwrap();
@@ -55,21 +92,53 @@ fn main() -> Result<()> {
             }
         }
         "build" => {
-            source::prechecks()?;
-            let content_cache = source::scan_cache()?;
-            let work_count = source::markdown_to_html_export(content_cache)?;
-            for entry in WalkDir::new(TEMPLATES_DIR) {
-                templates::template_engine(&entry?.into_path());
-            }
-            source::copy_assets(ASSETS)?;
-            match work_count {
-                0 => {
-                    logging::info("All files are already up to date.");
+            let mut watch = false;
+            let mut options = BuildOptions { mode: RenderMode::Production, drafts: false, future: false };
+            for argument in arguments {
+                match argument.as_ref() {
+                    "--watch" | "-w" => watch = true,
+                    "--drafts" | "-d" => options.drafts = true,
+                    "--future" | "-f" => options.future = true,
+                    _ => {
+                        usage(&program);
+                        logging::error(format!("unknown option `{}` for build.", argument).as_str());
+                        std::process::exit(1);
+                    }
                 }
-                _ => {
-                    logging::info(format!("{} files were affected.", work_count).as_str());
+            }
+            build(options)?;
+            if watch {
+                watch::watch(options)?;
+            }
+        }
+        "serve" => {
+            let mut port = server::DEFAULT_PORT;
+            while let Some(argument) = arguments.next() {
+                match argument.as_ref() {
+                    "--port" | "-p" => {
+                        port = arguments
+                            .next()
+                            .and_then(|value| value.parse().ok())
+                            .unwrap_or_else(|| {
+                                logging::error("expected a port number after `--port`.");
+                                std::process::exit(1);
+                            });
+                    }
+                    _ => {
+                        usage(&program);
+                        logging::error(format!("unknown option `{}` for serve.", argument).as_str());
+                        std::process::exit(1);
+                    }
                 }
             }
+            let options = BuildOptions { mode: RenderMode::LiveReload, drafts: true, future: true };
+            build(options)?;
+            std::thread::spawn(move || {
+                if let Err(err) = watch::watch(options) {
+                    logging::error(format!("stopped watching for changes: {}", err).as_str());
+                }
+            });
+            server::serve(port)?;
         }
         "version" => {
             println!("migi v0.1.2")
@@ -1,6 +1,6 @@
-use crate::logging;
+use crate::{logging, CONFIG};
 use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag};
-use std::{borrow::Cow, path::{PathBuf, Path}, panic};
+use std::{borrow::Cow, collections::BTreeMap, path::Path, panic};
 use syntect::{
     highlighting::ThemeSet,
     html::{ClassStyle, ClassedHTMLGenerator},
@@ -9,12 +9,47 @@ use syntect::{
 };
 use serde::{Deserialize, Serialize};
 use std::fs;
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 
 #[derive(Deserialize, Serialize)]
 pub struct Config {
+    /// The address the site is published at, e.g. `https://example.com`; feeds need absolute urls.
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Who the site's feeds are by, for posts without an `author`; defaults to `title`.
+    #[serde(default)]
+    pub author: Option<String>,
     themes: Themes,
     pub tagging: Tagging,
+    #[serde(default)]
+    pub urls: Urls,
+    #[serde(default)]
+    pub pagination: Pagination,
+    /// Groupings of posts besides `tags`, keyed by name, e.g. `[taxonomies.series]`.
+    #[serde(default)]
+    pub taxonomies: BTreeMap<String, TaxonomyConfig>,
+    /// Atom, RSS and JSON feeds, written only when this section exists.
+    #[serde(default)]
+    pub feeds: Option<Feeds>,
+    /// Write `index.json` describing every post, on unless set to `false`.
+    #[serde(default = "enabled")]
+    pub site_index: bool,
+    #[serde(default)]
+    pub sitemap: Sitemap,
+    /// Write `search-index.json` and the `search.js` widget that queries it.
+    #[serde(default)]
+    pub search: bool,
+}
+
+impl Config {
+    pub fn read() -> Result<Config> {
+        let config_file = fs::read_to_string(CONFIG)?;
+        toml::from_str(&config_file).with_context(|| format!("`{CONFIG}` is invalid"))
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -24,7 +59,127 @@ struct Themes {
 
 #[derive(Deserialize, Serialize)]
 pub struct Tagging {
+    /// Sort posts by their source path when no `sort_by` is given.
+    #[serde(default)]
     pub sorted: bool,
+    /// The `page` field posts are ordered by in `pages`, `tags` and listings: `date`, `title`,
+    /// `weight`, `path` or any other front matter field.
+    #[serde(default)]
+    pub sort_by: Option<String>,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// The markup of a single post in a `{{ tag }}` listing, rendered with `page` and `root`.
+    #[serde(default = "default_listing_item")]
+    pub item: String,
+}
+
+impl Tagging {
+    pub fn sort_key(&self) -> Option<&str> {
+        match &self.sort_by {
+            Some(key) => Some(key),
+            None if self.sorted => Some("path"),
+            None => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    #[serde(alias = "ascending")]
+    Asc,
+    #[serde(alias = "descending")]
+    Desc,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct Urls {
+    /// Write posts to `page/<name>/index.html` and link to them as `page/<name>/`.
+    #[serde(default)]
+    pub pretty: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct TaxonomyConfig {
+    /// The front matter field holding a post's terms; the taxonomy's name by default.
+    #[serde(default)]
+    pub field: Option<String>,
+    /// Rendered once per term; `<name>/term.html` by default (`tag.html` for tags).
+    #[serde(default)]
+    pub term_template: Option<String>,
+    /// Rendered once, listing every term; `<name>/terms.html` by default (`tags.html` for tags).
+    #[serde(default)]
+    pub index_template: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Feeds {
+    #[serde(default = "enabled")]
+    pub atom: bool,
+    #[serde(default = "enabled")]
+    pub rss: bool,
+    /// A JSON Feed, `feed.json`.
+    #[serde(default = "enabled")]
+    pub json: bool,
+    /// Also write feeds for every tag, next to its page (`tags/<tag>/atom.xml`).
+    #[serde(default = "enabled")]
+    pub tags: bool,
+    /// How many of the newest posts each feed contains.
+    #[serde(default = "default_feed_limit")]
+    pub limit: usize,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Sitemap {
+    /// Write `sitemap.xml`, as long as `base_url` is set.
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    /// Also write a `robots.txt` allowing everything and pointing at the sitemap.
+    #[serde(default)]
+    pub robots: bool,
+}
+
+impl Default for Sitemap {
+    fn default() -> Self {
+        Sitemap { enabled: true, robots: false }
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn default_feed_limit() -> usize {
+    20
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Pagination {
+    /// How many posts each page of a template using `paginator` shows.
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+    /// The directory later pages are written to, e.g. `page` for `page/2/index.html`.
+    #[serde(default = "default_pagination_path")]
+    pub path: String,
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Pagination { per_page: default_per_page(), path: default_pagination_path() }
+    }
+}
+
+fn default_per_page() -> usize {
+    10
+}
+
+fn default_pagination_path() -> String {
+    "page".to_string()
+}
+
+fn default_listing_item() -> String {
+    r#"<li><a href="{{ root }}{{ page.url }}">{{ page.title | escape }}</a></li>"#.to_string()
 }
 
 fn syntect_highlight<'a>( code_snippet: String,
@@ -59,7 +214,7 @@ fn syntect_highlight<'a>( code_snippet: String,
     let highlighted_result_type = syntect::html::highlighted_html_for_string(
         &code_snippet,
         &syntax_set,
-        &syntax,
+        syntax,
         &user_theme.unwrap(),
     );
 
@@ -68,6 +223,48 @@ fn syntect_highlight<'a>( code_snippet: String,
     ))
 }
 
+/// The words of a post without markup, e.g. for a search index. Code is kept, as readers search
+/// for function names too.
+pub fn plain_text(markdown_input: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new_ext(markdown_input, Options::all()) {
+        match event {
+            Event::Text(words) | Event::Code(words) => text.push_str(&words),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// The plain text of a post's summary: everything before a `<!-- more -->` line, else its first
+/// paragraph.
+pub fn summary_text(markdown_input: &str) -> String {
+    let text = match markdown_input.split_once("<!-- more -->") {
+        Some((before, _)) => plain_text(before),
+        None => {
+            let mut text = String::new();
+            let mut in_paragraph = false;
+            for event in Parser::new_ext(markdown_input, Options::all()) {
+                match event {
+                    Event::Start(Tag::Paragraph) => in_paragraph = true,
+                    Event::End(Tag::Paragraph) => break,
+                    Event::Text(words) | Event::Code(words) if in_paragraph => text.push_str(&words),
+                    Event::SoftBreak | Event::HardBreak if in_paragraph => text.push(' '),
+                    _ => {}
+                }
+            }
+            text
+        }
+    };
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether a post has code blocks, which are highlighted with the syntax theme.
+pub fn has_code_blocks(markdown_input: &str) -> bool {
+    Parser::new_ext(markdown_input, Options::all()).any(|event| matches!(event, Event::Start(Tag::CodeBlock(_))))
+}
+
 pub fn compile(markdown_input: &str) -> String {
     let parser_options = Options::all();
     let parser = Parser::new_ext(markdown_input, parser_options);
@@ -80,11 +277,8 @@ pub fn compile(markdown_input: &str) -> String {
     for event in parser.into_iter() {
         match event {
             Event::Start(Tag::CodeBlock(fenced_snippet)) => {
-                match fenced_snippet {
-                    CodeBlockKind::Fenced(language) => {
-                        language_name = language.to_string();
-                    }
-                    _ => {}
+                if let CodeBlockKind::Fenced(language) = fenced_snippet {
+                    language_name = language.to_string();
                 }
                 is_code_block = true;
             }
@@ -110,29 +304,3 @@ pub fn compile(markdown_input: &str) -> String {
 
     string_holder
 }
-
-pub fn remove_header(file_name: &PathBuf, file_content: &mut String) {
-    let mut in_block_toggle = 0;
-    let mut cursor = 0;
-
-    for line in file_content.lines() {
-        if line.starts_with("---") {
-            in_block_toggle += 1;
-            cursor += line.len();
-            continue;
-        }
-        if in_block_toggle >= 2 {
-            cursor += 4;
-            break;
-        }
-        if in_block_toggle == 1 {
-            cursor += line.len();
-        }
-    }
-
-    if in_block_toggle == 0 {
-        logging::warn(format!("`{}` does not contain a valid header", &file_name.to_string_lossy()).as_str());
-    }
-
-    *file_content = file_content.split_off(cursor)
-}
@@ -0,0 +1,56 @@
+use crate::{logging, BUILD_DIR, OUTPUTS};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The files under `build/` generated by a build (everything but copied assets), so the next
+/// build can remove the ones it no longer generates: the tag page of a tag whose posts were
+/// deleted or unpublished, pages past the new last page of a paginated template, ...
+#[derive(Serialize, Deserialize, Default)]
+pub struct Outputs(RefCell<BTreeSet<PathBuf>>);
+
+impl Outputs {
+    /// The outputs of the last build; empty when there was none yet.
+    pub fn read() -> Outputs {
+        fs::read_to_string(OUTPUTS)
+            .ok()
+            .and_then(|outputs| serde_json::from_str(&outputs).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn contains(&self, output: &Path) -> bool {
+        self.0.borrow().contains(output)
+    }
+
+    pub fn record(&self, output: &Path) {
+        self.0.borrow_mut().insert(output.to_path_buf());
+    }
+
+    /// Remove every output of `previous` that was not recorded again, along with the directories
+    /// left empty, and write the recorded outputs for the next build.
+    pub fn replace(self, previous: Outputs) -> Result<()> {
+        let current = self.0.borrow();
+        for output in previous.0.borrow().difference(&current) {
+            if !output.is_file() {
+                continue;
+            }
+            fs::remove_file(output)?;
+            logging::info(format!("removed stale {:?}", output).as_str());
+            for directory in output.ancestors().skip(1) {
+                if !directory.starts_with(BUILD_DIR) || directory == Path::new(BUILD_DIR) || fs::remove_dir(directory).is_err() {
+                    break;
+                }
+            }
+        }
+        if let Some(parent) = Path::new(OUTPUTS).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(OUTPUTS, serde_json::to_string_pretty(&*current)?)?;
+        Ok(())
+    }
+}
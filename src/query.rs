@@ -0,0 +1,208 @@
+use anyhow::{anyhow, bail, Result};
+
+/// Which posts a tag listing shows, e.g. `rust AND NOT (wasm OR web) first 5`.
+///
+/// Words next to each other without an operator are OR-ed, so `rust wasm` keeps meaning "tagged
+/// `rust` or `wasm`". `AND` binds tighter than `OR`, `NOT` tighter than both; the operators are
+/// only recognised in upper case so tags like `and` still work.
+#[derive(Debug, PartialEq)]
+pub struct TagQuery {
+    pub filter: Filter,
+    /// At most this many posts, from a trailing `first <n>`.
+    pub first: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Filter {
+    Tag(String),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+impl TagQuery {
+    pub fn parse(query: &str) -> Result<TagQuery> {
+        let mut tokens = tokenize(query);
+        let mut first = None;
+        if let [.., keyword, count] = tokens.as_slice() {
+            if keyword == "first" {
+                if let Ok(count) = count.parse() {
+                    first = Some(count);
+                    tokens.truncate(tokens.len() - 2);
+                }
+            }
+        }
+
+        let mut parser = Parser { tokens: &tokens, position: 0 };
+        let filter = parser.or()?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected `{token}` in tag query `{query}`");
+        }
+        Ok(TagQuery { filter, first })
+    }
+}
+
+impl Filter {
+    /// Selects posts with any of the terms, or `None` when there are none.
+    pub fn any_of(terms: Vec<String>) -> Option<Filter> {
+        terms
+            .into_iter()
+            .map(Filter::Tag)
+            .reduce(|left, right| Filter::Or(Box::new(left), Box::new(right)))
+    }
+
+    /// Whether a post with these tags is selected. Tags are compared ignoring case, so `Rust` in a
+    /// query matches a post tagged `rust`, but `c` does not match `C++`.
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            Filter::Tag(tag) => tags.iter().any(|other| other.to_lowercase() == tag.to_lowercase()),
+            Filter::Not(filter) => !filter.matches(tags),
+            Filter::And(left, right) => left.matches(tags) && right.matches(tags),
+            Filter::Or(left, right) => left.matches(tags) || right.matches(tags),
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Vec<String> {
+    query
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&str> {
+        let token = self.tokens.get(self.position)?;
+        self.position += 1;
+        Some(token)
+    }
+
+    /// `and (OR? and)*`
+    fn or(&mut self) -> Result<Filter> {
+        let mut filter = self.and()?;
+        loop {
+            match self.peek() {
+                Some("OR") => {
+                    self.position += 1;
+                }
+                Some(token) if token != ")" => {}
+                _ => return Ok(filter),
+            }
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+    }
+
+    /// `not (AND not)*`
+    fn and(&mut self) -> Result<Filter> {
+        let mut filter = self.not()?;
+        while self.peek() == Some("AND") {
+            self.position += 1;
+            filter = Filter::And(Box::new(filter), Box::new(self.not()?));
+        }
+        Ok(filter)
+    }
+
+    /// `NOT* atom`
+    fn not(&mut self) -> Result<Filter> {
+        if self.peek() == Some("NOT") {
+            self.position += 1;
+            return Ok(Filter::Not(Box::new(self.not()?)));
+        }
+        self.atom()
+    }
+
+    /// `tag | ( or )`
+    fn atom(&mut self) -> Result<Filter> {
+        match self.next() {
+            Some("(") => {
+                let filter = self.or()?;
+                match self.next() {
+                    Some(")") => Ok(filter),
+                    _ => Err(anyhow!("missing `)` in tag query")),
+                }
+            }
+            Some(token @ (")" | "AND" | "OR")) => Err(anyhow!("expected a tag before `{token}` in tag query")),
+            Some(tag) => Ok(Filter::Tag(tag.to_string())),
+            None => Err(anyhow!("tag query ends where a tag was expected")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str) -> Box<Filter> {
+        Box::new(Filter::Tag(name.to_string()))
+    }
+
+    #[test]
+    fn juxtaposed_tags_are_or_ed_below_and() {
+        let query = TagQuery::parse("rust wasm AND web").unwrap();
+        assert_eq!(query.filter, Filter::Or(tag("rust"), Box::new(Filter::And(tag("wasm"), tag("web")))));
+        assert_eq!(query.first, None);
+    }
+
+    #[test]
+    fn not_binds_to_the_next_tag_only() {
+        let query = TagQuery::parse("rust NOT wasm").unwrap();
+        assert_eq!(query.filter, Filter::Or(tag("rust"), Box::new(Filter::Not(tag("wasm")))));
+
+        let query = TagQuery::parse("NOT rust AND wasm").unwrap();
+        assert_eq!(query.filter, Filter::And(Box::new(Filter::Not(tag("rust"))), tag("wasm")));
+    }
+
+    #[test]
+    fn parentheses_group() {
+        let query = TagQuery::parse("rust AND NOT (wasm OR web)").unwrap();
+        let grouped = Filter::Or(tag("wasm"), tag("web"));
+        assert_eq!(query.filter, Filter::And(tag("rust"), Box::new(Filter::Not(Box::new(grouped)))));
+    }
+
+    #[test]
+    fn trailing_first_limits_the_listing() {
+        let query = TagQuery::parse("rust wasm first 5").unwrap();
+        assert_eq!(query.filter, Filter::Or(tag("rust"), tag("wasm")));
+        assert_eq!(query.first, Some(5));
+
+        // Without a number it is just a tag.
+        let query = TagQuery::parse("rust first").unwrap();
+        assert_eq!(query.filter, Filter::Or(tag("rust"), tag("first")));
+        assert_eq!(query.first, None);
+    }
+
+    #[test]
+    fn lower_case_operators_are_tags() {
+        let query = TagQuery::parse("rust and wasm").unwrap();
+        assert_eq!(query.filter, Filter::Or(Box::new(Filter::Or(tag("rust"), tag("and"))), tag("wasm")));
+    }
+
+    #[test]
+    fn malformed_queries_are_errors() {
+        assert_eq!(TagQuery::parse("( rust").unwrap_err().to_string(), "missing `)` in tag query");
+        assert_eq!(TagQuery::parse("rust )").unwrap_err().to_string(), "unexpected `)` in tag query `rust )`");
+        assert_eq!(TagQuery::parse("rust AND").unwrap_err().to_string(), "tag query ends where a tag was expected");
+        assert_eq!(TagQuery::parse("OR rust").unwrap_err().to_string(), "expected a tag before `OR` in tag query");
+        assert_eq!(TagQuery::parse("first 5").unwrap_err().to_string(), "tag query ends where a tag was expected");
+    }
+
+    #[test]
+    fn matches_tags_ignoring_case_only() {
+        let tags = ["Rust".to_string(), "C++".to_string()];
+        assert!(TagQuery::parse("rust").unwrap().filter.matches(&tags));
+        assert!(TagQuery::parse("c++ AND NOT wasm").unwrap().filter.matches(&tags));
+        assert!(!TagQuery::parse("c").unwrap().filter.matches(&tags));
+        assert!(!TagQuery::parse("rust AND wasm").unwrap().filter.matches(&tags));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{default, feeds::Contents, BUILD_DIR};
+use anyhow::Result;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tera::Value;
+
+/// A term in a post's title counts as this many occurrences in its text.
+const TITLE_WEIGHT: usize = 10;
+/// How much of a post's text is kept as its result snippet when it has no `description`.
+const SNIPPET_LENGTH: usize = 160;
+/// Suffixes `stem` replaces, longest first, and what replaces them.
+const SUFFIXES: [(&str, &str); 10] = [
+    ("ations", "ate"),
+    ("ation", "ate"),
+    ("ingly", ""),
+    ("ings", ""),
+    ("ing", ""),
+    ("edly", ""),
+    ("ies", "y"),
+    ("ied", "y"),
+    ("ed", ""),
+    ("ly", ""),
+];
+/// Letters whose doubling `undouble` keeps, as in `fall` or `buzz`.
+const KEEP_DOUBLED: &str = "aeiouylsz";
+
+/// Write `search-index.json` for `pages` and the `search.js` widget that queries it.
+///
+/// The index keeps a short entry per post (`url`, `title`, `snippet`) and maps every stemmed term
+/// to a flat `[post, weight, post, weight, ..]` list, so it stays small enough to download whole.
+pub fn write(contents: &Contents, pages: &[Value]) -> Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    let mut terms: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (number, page) in pages.iter().enumerate() {
+        let title = page["title"].as_str().unwrap_or_default();
+        let text = contents.text(page)?;
+
+        let mut weights: BTreeMap<String, usize> = BTreeMap::new();
+        for term in tokenize(title) {
+            *weights.entry(term).or_default() += TITLE_WEIGHT;
+        }
+        for term in tokenize(&text) {
+            *weights.entry(term).or_default() += 1;
+        }
+        for (term, weight) in weights {
+            terms.entry(term).or_default().extend([number, weight]);
+        }
+
+        let snippet = match page["description"].as_str() {
+            Some(description) if !description.trim().is_empty() => description.to_string(),
+            _ => snippet(&text),
+        };
+        entries.push(serde_json::json!({
+            "url": page["url"],
+            "title": title,
+            "snippet": snippet,
+        }));
+    }
+
+    let index = serde_json::json!({ "pages": entries, "terms": terms });
+    let written = [Path::new(BUILD_DIR).join("search-index.json"), Path::new(BUILD_DIR).join("search.js")];
+    fs::write(&written[0], serde_json::to_string(&index)?)?;
+    fs::write(&written[1], script()?)?;
+    Ok(written.into())
+}
+
+/// The search widget, stemming queries with the same tables as `stem`.
+fn script() -> Result<String> {
+    Ok(default::get_search_script()
+        .replace("__SUFFIXES__", &serde_json::to_string(&SUFFIXES)?)
+        .replace("__KEEP_DOUBLED__", &serde_json::to_string(KEEP_DOUBLED)?))
+}
+
+/// The stemmed, lower case words of `text`. Must stay in step with `tokenize` in `search.js`,
+/// which stems queries the same way (and gets `SUFFIXES` and `KEEP_DOUBLED` from here).
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(|word| stem(&word.to_lowercase()))
+        .collect()
+}
+
+/// A light English stemmer: strips the most common suffixes so `tags`, `tagged` and `tagging` all
+/// find `tag`, without the size of a full Porter stemmer in the widget.
+fn stem(word: &str) -> String {
+    for (suffix, replacement) in SUFFIXES {
+        if let Some(base) = word.strip_suffix(suffix) {
+            if base.chars().count() < 3 {
+                continue;
+            }
+            let mut stemmed = undouble(base);
+            stemmed.push_str(replacement);
+            return stemmed;
+        }
+    }
+    match word.strip_suffix('s') {
+        Some(base) if base.chars().count() >= 3 && !["s", "u", "i"].iter().any(|end| base.ends_with(end)) => {
+            base.to_string()
+        }
+        _ => word.to_string(),
+    }
+}
+
+/// `runn` -> `run`, but `fall` and `buzz` stay as they are.
+fn undouble(base: &str) -> String {
+    let chars: Vec<char> = base.chars().collect();
+    match chars.as_slice() {
+        [.., a, b] if a == b && !KEEP_DOUBLED.contains(*b) => chars[..chars.len() - 1].iter().collect(),
+        _ => base.to_string(),
+    }
+}
+
+fn snippet(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(SNIPPET_LENGTH) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stems_every_suffix() {
+        for (word, stemmed) in [
+            ("relations", "relate"),
+            ("creation", "create"),
+            ("amazingly", "amaz"),
+            ("paintings", "paint"),
+            ("painting", "paint"),
+            ("reportedly", "report"),
+            ("stories", "story"),
+            ("studied", "study"),
+            ("painted", "paint"),
+            ("quickly", "quick"),
+            ("posts", "post"),
+        ] {
+            assert_eq!(stem(word), stemmed, "{word}");
+        }
+    }
+
+    #[test]
+    fn keeps_suffixes_of_short_words() {
+        for (word, stemmed) in [("sing", "sing"), ("bed", "bed"), ("fly", "fly"), ("ties", "tie"), ("bus", "bus")] {
+            assert_eq!(stem(word), stemmed, "{word}");
+        }
+    }
+
+    #[test]
+    fn undoubles_final_consonants_except_keep_doubled() {
+        for (word, stemmed) in [
+            ("running", "run"),
+            ("tagged", "tag"),
+            ("stopped", "stop"),
+            ("falling", "fall"),
+            ("buzzing", "buzz"),
+            ("kissed", "kiss"),
+            ("seeing", "see"),
+        ] {
+            assert_eq!(stem(word), stemmed, "{word}");
+        }
+    }
+
+    #[test]
+    fn keeps_words_that_only_look_plural() {
+        for word in ["class", "status", "analysis", "gas"] {
+            assert_eq!(stem(word), word);
+        }
+    }
+
+    #[test]
+    fn tokenizes_into_stemmed_lower_case_words() {
+        assert_eq!(tokenize("Tagging, TAGS & a tagged_post!"), ["tag", "tag", "tag", "post"]);
+    }
+
+    #[test]
+    fn script_stems_with_the_same_tables() {
+        let script = script().unwrap();
+        assert!(script.contains(r#"var SUFFIXES = [["ations","ate"],["ation","ate"],"#), "{script}");
+        assert!(script.contains(r#"var KEEP_DOUBLED = "aeiouylsz";"#));
+        assert!(!script.contains("__"));
+    }
+}
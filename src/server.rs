@@ -0,0 +1,194 @@
+use crate::{logging, BUILD_DIR};
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use std::{
+    fs::File,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+use tiny_http::{Header, Request, Response, Server, StatusCode};
+
+pub const DEFAULT_PORT: u16 = 8080;
+const RELOAD_ENDPOINT: &str = "/__migi/reload";
+const RELOAD_SCRIPT: &str = r#"<script>new EventSource("/__migi/reload").onmessage=function(){location.reload()};</script>"#;
+
+/// Incremented after every finished rebuild; reload streams wait on it to change.
+static BUILD_GENERATION: Lazy<(Mutex<u64>, Condvar)> = Lazy::new(|| (Mutex::new(0), Condvar::new()));
+
+/// Tell every connected browser to reload.
+pub fn notify_reload() {
+    let (generation, changed) = &*BUILD_GENERATION;
+    *generation.lock().unwrap() += 1;
+    changed.notify_all();
+}
+
+/// Add the reload script to a rendered page, just before `</body>` when there is one.
+pub fn inject_live_reload(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(index) => format!("{}{RELOAD_SCRIPT}{}", &html[..index], &html[index..]),
+        None => format!("{html}{RELOAD_SCRIPT}"),
+    }
+}
+
+/// Serve the contents of `BUILD_DIR` on localhost until the process is interrupted.
+///
+/// Directories resolve to their `index.html`, and a directory requested without a trailing slash
+/// is redirected to the slash form so relative links (e.g. `../assets/css/style.css`) resolve the
+/// same way they would on a regular web server.
+pub fn serve(port: u16) -> Result<()> {
+    let address = format!("127.0.0.1:{port}");
+    let server = Server::http(&address).map_err(|e| anyhow!("could not bind to {address}: {e}"))?;
+    logging::info(format!("serving `{BUILD_DIR}` at http://{address}/ (press Ctrl-C to stop)").as_str());
+
+    for request in server.incoming_requests() {
+        // Reload streams stay open until the next rebuild, so each request gets its own thread.
+        thread::spawn(move || {
+            if let Err(err) = handle_request(request) {
+                logging::warn(format!("failed to respond to request: {err}").as_str());
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_request(request: Request) -> Result<()> {
+    let url_path = request.url().split(['?', '#']).next().unwrap_or("/").to_string();
+    if url_path == RELOAD_ENDPOINT {
+        return stream_reload_events(request);
+    }
+    let Some(relative_path) = resolve_url(&url_path) else {
+        return respond_not_found(request, &url_path);
+    };
+    let mut file_path = Path::new(BUILD_DIR).join(relative_path);
+
+    if file_path.is_dir() {
+        if !url_path.ends_with('/') {
+            let location = Header::from_bytes("Location", format!("{url_path}/")).unwrap();
+            let response = Response::empty(StatusCode(301)).with_header(location);
+            return Ok(request.respond(response)?);
+        }
+        file_path.push("index.html");
+    }
+
+    match File::open(&file_path) {
+        Ok(file) if file_path.is_file() => {
+            let content_type = Header::from_bytes("Content-Type", mime_type(&file_path)).unwrap();
+            Ok(request.respond(Response::from_file(file).with_header(content_type))?)
+        }
+        _ => respond_not_found(request, &url_path),
+    }
+}
+
+/// Hold a server-sent events stream open until the next rebuild finishes, then send one `reload`
+/// event and close it. Periodic comments let us notice (and drop) streams of closed tabs.
+fn stream_reload_events(request: Request) -> Result<()> {
+    let mut writer = request.into_writer();
+    write!(
+        writer,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    writer.flush()?;
+
+    let (generation, changed) = &*BUILD_GENERATION;
+    let mut current = generation.lock().unwrap();
+    let connected_at = *current;
+    loop {
+        let (guard, timeout) = changed.wait_timeout(current, Duration::from_secs(15)).unwrap();
+        current = guard;
+        if *current != connected_at {
+            writer.write_all(b"data: reload\n\n")?;
+            writer.flush()?;
+            return Ok(());
+        }
+        if timeout.timed_out() {
+            writer.write_all(b": ping\n\n")?;
+            writer.flush()?;
+        }
+    }
+}
+
+fn respond_not_found(request: Request, url_path: &str) -> Result<()> {
+    logging::warn(format!("404: {url_path}").as_str());
+    let content_type = Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap();
+    let not_found_page = Path::new(BUILD_DIR).join("404.html");
+
+    if let Ok(file) = File::open(not_found_page) {
+        let response = Response::from_file(file)
+            .with_status_code(404)
+            .with_header(content_type);
+        return Ok(request.respond(response)?);
+    }
+    let response = Response::from_string("<h1>404 Not Found</h1>")
+        .with_status_code(404)
+        .with_header(content_type);
+    Ok(request.respond(response)?)
+}
+
+/// Turn a request path into a path relative to `BUILD_DIR`, refusing anything that would escape it.
+fn resolve_url(url_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(url_path)?;
+    let mut resolved = PathBuf::new();
+
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(resolved)
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = input.get(index + 1..index + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
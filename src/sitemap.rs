@@ -0,0 +1,42 @@
+use crate::{feeds::escape, frontmatter::parse_date, BUILD_DIR};
+use anyhow::Result;
+use chrono::SecondsFormat;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tera::Value;
+
+/// Write `sitemap.xml` for `entries` of site-root relative urls and their last change, and with
+/// `robots` a `robots.txt` pointing at it.
+pub fn write(base_url: &str, entries: &[(String, Option<&str>)], robots: bool) -> Result<Vec<PathBuf>> {
+    let base_url = base_url.trim_end_matches('/');
+    let mut sitemap = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (url, updated) in entries {
+        sitemap += &format!("<url>\n<loc>{}</loc>\n", escape(&format!("{base_url}/{url}")));
+        if let Some(updated) = updated.and_then(parse_date) {
+            sitemap += &format!("<lastmod>{}</lastmod>\n", updated.to_rfc3339_opts(SecondsFormat::Secs, true));
+        }
+        sitemap += "</url>\n";
+    }
+    sitemap += "</urlset>\n";
+    let mut written = vec![Path::new(BUILD_DIR).join("sitemap.xml")];
+    fs::write(&written[0], sitemap)?;
+
+    if robots {
+        let output = Path::new(BUILD_DIR).join("robots.txt");
+        fs::write(&output, format!("User-agent: *\nAllow: /\n\nSitemap: {base_url}/sitemap.xml\n"))?;
+        written.push(output);
+    }
+    Ok(written)
+}
+
+/// When the newest of `pages` last changed, as a page listing them changes with them.
+pub fn newest(pages: &[Value]) -> Option<&str> {
+    pages
+        .iter()
+        .filter_map(|page| page["updated"].as_str())
+        .max_by_key(|updated| parse_date(updated))
+}
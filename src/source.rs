@@ -1,37 +1,166 @@
 use walkdir::WalkDir;
-use anyhow::Result;
-use crate::{default, PAGE_TEMPLATE, logging, CONTENT, BUILD_DIR, PAGE_BUILD_DIR, cache::{DataManager, self}, markdown, CONTENT_CACHE};
+use anyhow::{bail, Result};
+use crate::{default, PAGE_TEMPLATE, logging, CONTENT, BUILD_DIR, PAGE_BUILD_DIR, cache::{CacheData, DataManager, self}, dependencies::Dependencies, frontmatter::FrontMatter, markdown::{self, Config}, server, templates, CONTENT_CACHE, BUILD_STAMP};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs::{self, create_dir_all},
     path::{PathBuf, Path},
 };
 
-pub fn markdown_to_html_export(content_cache: DataManager) -> Result<usize> {
+/// How generated html is rendered. `LiveReload` is only used by `migi serve` and injects the
+/// reload script into every page; `Production` output never contains it.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderMode {
+    #[default]
+    Production,
+    LiveReload,
+}
+
+impl RenderMode {
+    /// Apply mode specific changes to an already minified html document.
+    pub fn finish_html(self, html: &str) -> String {
+        match self {
+            RenderMode::Production => html.to_string(),
+            RenderMode::LiveReload => server::inject_live_reload(html),
+        }
+    }
+}
+
+/// What a build renders and which posts it publishes.
+#[derive(Clone, Copy, Debug)]
+pub struct BuildOptions {
+    pub mode: RenderMode,
+    /// Publish posts marked `draft: true`.
+    pub drafts: bool,
+    /// Publish posts dated in the future.
+    pub future: bool,
+}
+
+impl BuildOptions {
+    /// Whether a post is rendered and listed in this build.
+    pub fn publishes(&self, entry: &CacheData) -> bool {
+        (self.drafts || !entry.draft) && (self.future || !entry.is_scheduled())
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+struct BuildStamp {
+    mode: RenderMode,
+    #[serde(default)]
+    pretty_urls: bool,
+    #[serde(default)]
+    drafts: bool,
+    #[serde(default)]
+    future: bool,
+}
+
+impl BuildStamp {
+    fn new(options: BuildOptions, pretty_urls: bool) -> BuildStamp {
+        BuildStamp { mode: options.mode, pretty_urls, drafts: options.drafts, future: options.future }
+    }
+
+    /// Recorded by `markdown_to_html_export` once every page rendered, so a build that fails
+    /// halfway is compared against the last complete one.
+    fn write(&self) -> Result<()> {
+        if let Some(parent) = Path::new(BUILD_STAMP).parent() {
+            create_dir_all(parent)?;
+        }
+        fs::write(BUILD_STAMP, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Whether the render mode, url style or draft and future post handling of this build differs
+/// from the last complete build.
+///
+/// Pages are only re-rendered when their markdown changes, so without this a page built by
+/// `migi serve` would keep its reload script after a later `migi build`, switching url styles
+/// would leave links pointing at the old paths, and drafts would stay published.
+pub fn build_stamp_changed(options: BuildOptions) -> Result<bool> {
+    let previous: BuildStamp = fs::read_to_string(BUILD_STAMP)
+        .ok()
+        .and_then(|stamp| serde_json::from_str(&stamp).ok())
+        .unwrap_or_default();
+    Ok(previous != BuildStamp::new(options, Config::read()?.urls.pretty))
+}
+
+pub fn markdown_to_html_export(content_cache: DataManager, options: BuildOptions) -> Result<usize> {
     let mut work_count = 0;
-    let html_file_template = fs::read_to_string(PAGE_TEMPLATE).unwrap_or_else(|_| {
+    if !Path::new(PAGE_TEMPLATE).is_file() {
         logging::error("the template `page.html` could not be found.");
         std::process::exit(1);
-    });
-    for change_file in content_cache.required_changes.into_inner() {
-        let mut file_contents = fs::read_to_string(&change_file)?;
-        markdown::remove_header(&change_file, &mut file_contents);
-
-        let html_output = markdown::compile(&file_contents);
-        let file_name = html_file_name(&change_file);
-        let mut output_templates = html_file_template.replace("{{ content }}", &html_output);
-        let minify_html = minify_html_onepass::in_place_str(&mut output_templates, &minify_html_onepass::Cfg::new());
-        logging::info(format!("converted {:?} -> {:?}", &change_file, &file_name).as_str());
-        fs::write(format!("{PAGE_BUILD_DIR}/{}", file_name.to_string_lossy().to_string()), minify_html.unwrap())?;
+    }
+    let cache = content_cache.cache.borrow();
+    let site = templates::Site::load(&cache, options)?;
+    check_output_collisions(&cache, &site, options)?;
+
+    let mut changes = content_cache.required_changes.take();
+    let mut dependencies = Dependencies::read();
+    // Pages missing from the build are rendered too, e.g. scheduled posts that have come due
+    // without their markdown changing, and so are pages whose templates, config or theme changed.
+    for entry in cache.iter().filter(|entry| options.publishes(entry)) {
+        let missing = !entry.output_file(site.pretty_urls).is_file();
+        if (missing || dependencies.is_outdated(&entry.name, &site.page_dependencies(entry))) && !changes.contains(&entry.name) {
+            changes.push(entry.name.clone());
+        }
+    }
+    for change_file in changes {
+        let Some(entry) = CacheData::find(&cache, &change_file) else {
+            continue;
+        };
+        let output = entry.output_file(site.pretty_urls);
+        if !options.publishes(entry) {
+            // A draft or scheduled post published by an earlier `migi serve` must not linger.
+            if output.is_file() {
+                fs::remove_file(&output)?;
+                logging::info(format!("removed unpublished {:?}", &output).as_str());
+            }
+            dependencies.remove(&entry.name);
+            continue;
+        }
+
+        let file_contents = fs::read_to_string(&change_file)?;
+        let (_, markdown_body) = FrontMatter::parse(&change_file, &file_contents)?;
+        let html_output = markdown::compile(markdown_body);
+        if let Some(parent) = output.parent() {
+            create_dir_all(parent)?;
+        }
+        let rendered = site.render_page(entry, &html_output, &output)?;
+        logging::info(format!("converted {:?} -> {:?}", &change_file, &output).as_str());
+        fs::write(&output, templates::finish_output(&output, rendered, options.mode))?;
+        dependencies.record(&entry.name, site.page_dependencies(entry));
         work_count += 1;
     }
+    log_scheduled(&cache, options);
+    // Only persisted once every page rendered, so a failed build is retried next time.
+    content_cache.write_to_json()?;
+    dependencies.write(&cache)?;
+    BuildStamp::new(options, site.pretty_urls).write()?;
     Ok(work_count)
 }
 
+/// List the posts held back until their date, soonest first.
+fn log_scheduled(cache: &[CacheData], options: BuildOptions) {
+    let mut scheduled: Vec<&CacheData> = cache
+        .iter()
+        .filter(|entry| !options.future && entry.is_scheduled() && (options.drafts || !entry.draft))
+        .collect();
+    if scheduled.is_empty() {
+        return;
+    }
+    scheduled.sort_by_key(|entry| entry.date);
+    logging::info(format!("{} posts are scheduled (build with `--future` to publish them now):", scheduled.len()).as_str());
+    for entry in scheduled {
+        logging::info(format!("  {}  {}", entry.date.to_rfc3339(), entry.name.display()).as_str());
+    }
+}
+
 pub fn scan_cache() -> Result<DataManager> {
     let markdown_files = markdown_file_names()?;
     let mut content_cache = cache::CacheData::create_manager(markdown_files, CONTENT_CACHE)?;
     content_cache.process_data()?;
-    content_cache.write_to_json()?;
 
     Ok(content_cache)
 }
@@ -57,6 +186,9 @@ pub fn copy_assets(assets: &str) -> Result<()> {
         }
 
         if handle.is_file() {
+            if let Some(parent) = Path::new(&build_dir_path).parent() {
+                create_dir_all(parent)?;
+            }
             fs::copy(&handle, build_dir_path)?;
         }
     }
@@ -65,13 +197,13 @@ pub fn copy_assets(assets: &str) -> Result<()> {
 
 pub fn setup_new_project(project_name: &str) -> Result<()> {
     let root_directory = PathBuf::from(project_name);
-    let directories = vec!["content", "assets/syntax", "assets/css", "templates"];
+    let directories = vec!["content", "assets/syntax", "assets/css", "templates/partials"];
 
     fs::create_dir(&root_directory)?;
     logging::info(format!("created directory `./{}`", &root_directory.to_string_lossy()).as_str());
 
     for dir in directories {
-        let dir_path = root_directory.join(&PathBuf::from(dir));
+        let dir_path = root_directory.join(PathBuf::from(dir));
         fs::create_dir_all(&dir_path)?;
 
         logging::info(format!("created directory `./{}`", &dir_path.to_string_lossy()).as_str());
@@ -83,14 +215,22 @@ pub fn setup_new_project(project_name: &str) -> Result<()> {
         &root_directory.to_string_lossy()
         );
     let page_template = format!("{}/{}", &root_directory.to_string_lossy(), PAGE_TEMPLATE);
+    let base_template = format!("{}/templates/base.html", &root_directory.to_string_lossy());
+    let nav_partial = format!("{}/templates/partials/nav.html", &root_directory.to_string_lossy());
     let index_path = format!("{}/templates/index.html", &root_directory.to_string_lossy());
+    let tag_template = format!("{}/templates/tag.html", &root_directory.to_string_lossy());
+    let tags_template = format!("{}/templates/tags.html", &root_directory.to_string_lossy());
     let css_path = format!("{}/assets/css/style.css", &root_directory.to_string_lossy());
     let generic_post = format!("{}/content/first_post.md", &root_directory.to_string_lossy());
 
     fs::write(config_path, default::get_config())?;
     fs::write(theme_path, default::get_theme())?;
     fs::write(page_template, default::get_page_template())?;
+    fs::write(base_template, default::get_base_template())?;
+    fs::write(nav_partial, default::get_nav_partial())?;
     fs::write(index_path, default::get_index_template())?;
+    fs::write(tag_template, default::get_tag_template())?;
+    fs::write(tags_template, default::get_tags_template())?;
     fs::write(css_path, default::get_css())?;
     fs::write(generic_post, default::get_generic_post())?;
 
@@ -101,111 +241,65 @@ pub fn setup_new_project(project_name: &str) -> Result<()> {
 
 pub fn markdown_file_names() -> Result<Vec<PathBuf>> {
     let mut captured_vec: Vec<PathBuf> = Vec::new();
-    for entry in WalkDir::new(CONTENT) {
-        if let Ok(entry) = entry {
-            if let Some(ext) = entry.path().extension() {
-                if ext.to_str().unwrap() == "md" {
-                    captured_vec.push(entry.into_path());
-                } else {
-                    logging::warn(format!("file {} is not a markdown file and has been ignored.", entry.path().display()).as_str());
-                }
+    for entry in WalkDir::new(CONTENT).into_iter().flatten() {
+        if let Some(ext) = entry.path().extension() {
+            if ext.to_str().unwrap() == "md" {
+                captured_vec.push(entry.into_path());
+            } else {
+                logging::warn(format!("file {} is not a markdown file and has been ignored.", entry.path().display()).as_str());
             }
         }
     }
     Ok(captured_vec)
 }
 
-#[derive(Default, Debug)]
-pub struct HeaderParser {
-    pub title: String,
-    pub tags: Vec<String>,
-}
-
-impl HeaderParser {
-    fn get_header(text: &str) -> Option<Vec<String>> {
-        let mut yielded: Vec<String> = Vec::with_capacity(text.len());
-        let mut in_block_toggle = 0;
-
-        for line in text.lines() {
-            if line.starts_with("---") {
-                in_block_toggle += 1;
-                continue;
-            }
-            if in_block_toggle > 1 {
-                break;
-            }
-            if in_block_toggle == 1 {
-                yielded.push(line.to_string());
-            }
-        }
-        if yielded.is_empty() {
-            None
-        } else {
-            Some(yielded)
-        }
-    }
-    pub fn get_data(text: &str) -> Option<HeaderParser> {
-        let mut metadata = HeaderParser::default();
-        let mut has_valid_title = true;
-        if let Some(header_lines) = Self::get_header(&text) {
-            for line in header_lines {
-                if line.to_lowercase().starts_with("title:") {
-                    match HeaderParser::get_title(&line) {
-                        Some(title) => {
-                            metadata.title = title;
-                        }
-                        None => {
-                            has_valid_title = false;
-                            logging::error("Error: All files must contain a valid title.");
-                        }
-                    }
-                }
-                if line.starts_with("tags:") {
-                    if let Some(tag) = HeaderParser::get_tags(&line) {
-                        metadata.tags = tag;
-                    }
-                }
-            }
-        };
-        if has_valid_title {
-            return Some(metadata);
-        }
-        return None;
-    }
-
-    fn get_title(header: &str) -> Option<String> {
-        let index = header.find("title:").unwrap();
-        let title = header[(index+6)..].trim();
-        if !title.is_empty() {
-            return Some(title.to_owned());
+/// The path of a post's html relative to `PAGE_BUILD_DIR`, mirroring its directory under `CONTENT`
+/// (`content/2023/intro.md` becomes `2023/intro.html`, or `2023/intro/index.html` with pretty urls).
+/// An `index.md` is already directory-style and keeps its name either way.
+pub fn html_file_name(md_file_name: &Path, pretty_urls: bool) -> PathBuf {
+    let relative = md_file_name.strip_prefix(CONTENT).unwrap_or(md_file_name);
+    match relative.file_stem() {
+        Some(stem) if pretty_urls && stem != "index" => relative.with_extension("").join("index.html"),
+        Some(_) => relative.with_extension("html"),
+        None => {
+            logging::warn("issues stemming a file... skipping for now.");
+            Path::new("").to_path_buf()
         }
-        return None;
     }
+}
 
-    fn get_tags(header: &str) -> Option<Vec<String>> {
-        if let Some(index) = header.find("tags:") {
-            let tags: Vec<String> = header[(index+5)..]
-                .split_ascii_whitespace()
-                .map(|t| t.to_string())
-                .collect();
-            return Some(tags);
-        }
-
-        None
+/// The site-root relative url of a file under `BUILD_DIR`. Directory style urls point at the
+/// directory of an `index.html` (`page/intro/`) rather than at the file itself.
+pub fn output_url(output: &Path, directory_style: bool) -> String {
+    let url = output
+        .strip_prefix(BUILD_DIR)
+        .unwrap_or(output)
+        .to_string_lossy()
+        .replace('\\', "/");
+    match url.strip_suffix("index.html") {
+        Some(directory) if directory_style => directory.to_string(),
+        _ => url,
     }
 }
 
-pub fn html_file_name(md_file_name: &PathBuf) -> PathBuf {
-    let file_stemmed = md_file_name.file_stem().and_then(|stem| Some(stem.to_string_lossy().to_string()));
-    match file_stemmed {
-        Some(file) => {
-            let html_file = file + ".html";
-            let path = Path::new(&html_file).to_path_buf();
-            return path;
-        },
-        None => {
-            logging::warn("issues stemming a file... skipping for now.");
-            return Path::new("").to_path_buf();
+/// Fail when two posts, or a post and a template, would be written to the same file, instead of
+/// one silently replacing the other.
+pub fn check_output_collisions(cache: &[CacheData], site: &templates::Site, options: BuildOptions) -> Result<()> {
+    let template_outputs = site.template_outputs();
+    let mut outputs: HashMap<PathBuf, &Path> = HashMap::new();
+    for entry in cache.iter().filter(|entry| options.publishes(entry)) {
+        let output = entry.output_file(site.pretty_urls);
+        if template_outputs.contains(&output) {
+            bail!("`{}` would be overwritten by the template rendered to `{}`", entry.name.display(), output.display());
+        }
+        if let Some(other) = outputs.insert(output.clone(), &entry.name) {
+            bail!(
+                "`{}` and `{}` would both be written to `{}`",
+                other.display(),
+                entry.name.display(),
+                output.display()
+            );
         }
     }
+    Ok(())
 }
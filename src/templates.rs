@@ -1,82 +1,882 @@
 use crate::{
-    cache::CacheData,
-    source::html_file_name, CONTENT_CACHE,
-    logging, markdown::Config,
+    cache::{content_hash, CacheData},
+    dependencies::PageDependencies,
+    feeds::{self, Contents, FeedWriter},
+    frontmatter::{parse_date, terms_from_value},
+    search, sitemap,
+    source::{output_url, BuildOptions, RenderMode}, CONTENT_CACHE,
+    logging, markdown::{Config, SortOrder, TaxonomyConfig}, outputs::Outputs,
+    query::{Filter, TagQuery}, ASSETS, BUILD_DIR, CONFIG, PAGE_TEMPLATE, TEMPLATES_DIR,
 };
 use std::{
-    collections::HashSet,
-    fs::{self, write, File},
-    io::Read, path::{PathBuf, Path}
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::{self, write},
+    path::{Path, PathBuf},
 };
 use {once_cell::sync::Lazy, regex::Regex};
+use anyhow::{bail, Context as _, Result};
+use tera::{Context, Tera, Value};
+use walkdir::WalkDir;
 
-pub fn template_engine(change_file: &PathBuf) {
-    if change_file.is_dir() {
-        return;
+/// Variables every template is rendered with; a bare `{{ name }}` using one of these is never
+/// treated as a tag listing placeholder.
+const CONTEXT_VARIABLES: [&str; 11] =
+    ["page", "pages", "tags", "content", "root", "config", "paginator", "taxonomies", "taxonomy", "term", "terms"];
+/// Renders one page per tag, at `tags/<tag>/index.html`.
+const TAG_TEMPLATE: &str = "tag.html";
+/// Renders the index of every tag, at `tags/index.html`.
+const TAGS_TEMPLATE: &str = "tags.html";
+
+/// Everything templates are rendered from: the parsed templates and the posts in the cache.
+pub struct Site {
+    tera: Tera,
+    config: Value,
+    pub pretty_urls: bool,
+    pages: Vec<Value>,
+    tags: BTreeMap<String, Vec<Value>>,
+    /// Templates that only exist to be extended, included or imported by others.
+    shared: HashSet<String>,
+    /// Templates using `paginator`, rendered once per page of posts.
+    paginated: HashSet<String>,
+    /// `config.toml` as migi reads it; `config` is the same file as templates see it.
+    settings: Config,
+    /// `tags` followed by the taxonomies from `config.toml`.
+    taxonomies: Vec<Taxonomy>,
+    /// The source of every template by name, after upgrading legacy placeholders.
+    sources: HashMap<String, String>,
+    /// A hash of `pages`, which changes whenever any post a template could list does.
+    pages_hash: String,
+    /// Every file written under `build/` so far.
+    outputs: Outputs,
+    /// The posts' html and summaries for feeds, `index.json` and the search index.
+    contents: Contents,
+}
+
+/// A grouping of posts by the terms in one of their front matter fields, like `tags`.
+struct Taxonomy {
+    name: String,
+    field: String,
+    term_template: String,
+    index_template: String,
+    /// Every term with its posts, merged by slug and ordered by it.
+    terms: Vec<Term>,
+}
+
+impl Taxonomy {
+    fn new(name: &str, config: &TaxonomyConfig, pages: &[Value], pretty_urls: bool) -> Result<Taxonomy> {
+        let (term_template, index_template) = match name {
+            "tags" => (TAG_TEMPLATE.to_string(), TAGS_TEMPLATE.to_string()),
+            _ => (format!("{name}/term.html"), format!("{name}/terms.html")),
+        };
+        let mut taxonomy = Taxonomy {
+            name: name.to_string(),
+            field: config.field.clone().unwrap_or_else(|| name.to_string()),
+            term_template: config.term_template.clone().unwrap_or(term_template),
+            index_template: config.index_template.clone().unwrap_or(index_template),
+            terms: Vec::new(),
+        };
+
+        for page in pages {
+            for name in page_terms(&page[&taxonomy.field]) {
+                let slug = slug::slugify(&name);
+                if slug.is_empty() {
+                    bail!(
+                        "`{}` in `{}` of `{}` has no letters or digits to name its page after",
+                        name,
+                        taxonomy.field,
+                        page["path"].as_str().unwrap_or_default()
+                    );
+                }
+                // Terms differing only in case (`Rust`, `rust`) share a page; different terms
+                // with the same slug (`C++`, `C#`) would overwrite each other's.
+                match taxonomy.terms.iter_mut().find(|term| term.slug == slug) {
+                    Some(term) if term.name.to_lowercase() != name.to_lowercase() => bail!(
+                        "`{}` and `{}` in `{}` would both be written to `{}`",
+                        term.name,
+                        name,
+                        taxonomy.field,
+                        term.output.display()
+                    ),
+                    Some(term) if term.pages.contains(page) => {}
+                    Some(term) => term.pages.push(page.clone()),
+                    None => {
+                        let output = taxonomy.index_output().with_file_name(&slug).join("index.html");
+                        let url = output_url(&output, pretty_urls);
+                        taxonomy.terms.push(Term { name, slug, output, url, pages: vec![page.clone()] });
+                    }
+                }
+            }
+        }
+        taxonomy.terms.sort_by(|a, b| a.slug.cmp(&b.slug));
+        Ok(taxonomy)
+    }
+
+    /// Where the index of every term is written, e.g. `build/tags/index.html`.
+    fn index_output(&self) -> PathBuf {
+        Path::new(BUILD_DIR).join(&self.name).join("index.html")
     }
-    let stemmed_template_dir = change_file.to_string_lossy().to_string().replace("templates/", "build/");
-    let stemmed_path = Path::new(&stemmed_template_dir).parent().unwrap();
-    if !stemmed_path.exists() {
-        std::fs::create_dir_all(stemmed_path).unwrap_or_else(|e| {
-            logging::error(format!("could not create {}\n {}", stemmed_path.display(), e).as_str());
+
+    /// Every term without its posts, keyed by name.
+    fn summaries(&self) -> serde_json::Map<String, Value> {
+        self.terms.iter().map(|term| (term.name.clone(), term.summary())).collect()
+    }
+}
+
+/// A term of a taxonomy, e.g. a single tag, as seen by its term and index templates.
+struct Term {
+    name: String,
+    slug: String,
+    output: PathBuf,
+    url: String,
+    pages: Vec<Value>,
+}
+
+impl Term {
+    /// The term without its posts, as listed in `taxonomies` and `terms`.
+    fn summary(&self) -> Value {
+        serde_json::json!({ "name": self.name, "slug": self.slug, "url": self.url, "count": self.pages.len() })
+    }
+}
+
+impl Site {
+    /// Load the templates and the posts `options` publishes; unpublished drafts appear in no
+    /// `pages`, `tags` or listing.
+    pub fn load(cache: &[CacheData], options: BuildOptions) -> Result<Site> {
+        let config_file = fs::read_to_string(CONFIG)?;
+        let config: Config = toml::from_str(&config_file).with_context(|| format!("`{CONFIG}` is invalid"))?;
+        let config_value = serde_json::to_value(toml::from_str::<toml::Value>(&config_file)?)?;
+
+        let pretty_urls = config.urls.pretty;
+        let mut pages: Vec<Value> = cache
+            .iter()
+            .filter(|entry| options.publishes(entry))
+            .map(|entry| entry.page_variables(pretty_urls))
+            .collect();
+        if let Some(key) = config.tagging.sort_key() {
+            sort_pages(&mut pages, key, config.tagging.order);
+        }
+
+        let mut tags: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+        for page in &pages {
+            for tag in page["tags"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+                tags.entry(tag.to_string()).or_default().push(page.clone());
+            }
+        }
+
+        let tags_config = config.taxonomies.get("tags").cloned().unwrap_or_default();
+        let mut taxonomies = vec![Taxonomy::new("tags", &tags_config, &pages, pretty_urls)?];
+        for (name, taxonomy) in config.taxonomies.iter().filter(|(name, _)| *name != "tags") {
+            taxonomies.push(Taxonomy::new(name, taxonomy, &pages, pretty_urls)?);
+        }
+
+        let templates = read_templates(TEMPLATES_DIR)?;
+        let sources: HashMap<String, String> = templates.iter().cloned().collect();
+        let mut shared = included_templates(&templates);
+        shared.extend(page_layouts(&templates));
+        let paginated = paginated_templates(&templates);
+        let mut tera = escaping_tera();
+        tera.add_raw_templates(templates)?;
+        let mut item = escaping_tera();
+        item.add_raw_template("item.html", &config.tagging.item)
+            .context("`item` under `[tagging]` in `config.toml` is not a valid template")?;
+        tera.register_function("listing", Listing {
+            pages: pages.clone(),
+            item,
+            sort_by: config.tagging.sort_key().map(str::to_string),
+            fields: taxonomies.iter().map(|taxonomy| (taxonomy.name.clone(), taxonomy.field.clone())).collect(),
         });
+
+        for template in tera.templates.values() {
+            shared.extend(template.parents.iter().cloned());
+            shared.extend(template.imported_macro_files.iter().map(|(name, _)| name.clone()));
+        }
+        shared.extend(
+            tera.get_template_names()
+                .filter(|name| is_partial(name))
+                .map(str::to_string),
+        );
+        // Layouts picked by a post's `layout` field are page templates, not standalone pages.
+        shared.extend(cache.iter().map(CacheData::layout_template));
+        // Rendered once per term and into the taxonomy's directory instead, see `render_taxonomies`.
+        for taxonomy in &taxonomies {
+            shared.extend([taxonomy.term_template.clone(), taxonomy.index_template.clone()]);
+        }
+
+        Ok(Site {
+            tera,
+            config: config_value,
+            pretty_urls,
+            tags,
+            shared,
+            paginated,
+            taxonomies,
+            sources,
+            pages_hash: content_hash(serde_json::to_string(&pages)?.as_bytes()),
+            pages,
+            settings: config,
+            outputs: Outputs::default(),
+            contents: Contents::new(cache),
+        })
+    }
+
+    /// Everything besides its markdown that the page of `entry` is rendered from: its layout and
+    /// every template that pulls in, the config keys those templates read, the list of posts if
+    /// they list any, and the syntax theme if the post has code to highlight.
+    pub fn page_dependencies(&self, entry: &CacheData) -> PageDependencies {
+        static CONFIG_KEY: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bconfig\b(?:\.(\w+))?").unwrap());
+        static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)\{[{%](.*?)[%}]\}").unwrap());
+        static LISTS_POSTS: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:^|[^.\w])(pages|tags|taxonomies|listing)\b").unwrap());
+
+        let config_hash = |key: &str| {
+            let value = if key.is_empty() { &self.config } else { &self.config[key] };
+            content_hash(value.to_string().as_bytes())
+        };
+        let mut dependencies = PageDependencies::new();
+        for name in self.template_closure(&entry.layout_template()) {
+            let source = self.sources.get(&name).map(String::as_str).unwrap_or_default();
+            for tag in TAG.captures_iter(source) {
+                for key in CONFIG_KEY.captures_iter(&tag[1]) {
+                    let key = key.get(1).map_or("", |key| key.as_str());
+                    dependencies.insert(format!("config:{key}"), config_hash(key));
+                }
+                if LISTS_POSTS.is_match(&tag[1]) {
+                    dependencies.insert("pages".to_string(), self.pages_hash.clone());
+                    dependencies.insert("config:tagging".to_string(), config_hash("tagging"));
+                }
+            }
+            dependencies.insert(format!("template:{name}"), content_hash(source.as_bytes()));
+        }
+        if entry.code_blocks {
+            dependencies.insert("config:themes".to_string(), config_hash("themes"));
+            let theme = format!("{ASSETS}syntax/{}", self.config["themes"]["syntax"].as_str().unwrap_or_default());
+            let hash = fs::read(&theme).map(|theme| content_hash(&theme)).unwrap_or_default();
+            dependencies.insert(format!("asset:{theme}"), hash);
+        }
+        dependencies
+    }
+
+    /// `name` and every template it extends, includes or imports, directly or not.
+    fn template_closure(&self, name: &str) -> BTreeSet<String> {
+        let mut closure = BTreeSet::new();
+        let mut pending = vec![name.to_string()];
+        while let Some(name) = pending.pop() {
+            if let Some(template) = self.tera.templates.get(&name) {
+                pending.extend(template.parents.iter().cloned());
+                pending.extend(template.imported_macro_files.iter().map(|(file, _)| file.clone()));
+            }
+            if let Some(source) = self.sources.get(&name) {
+                pending.extend(template_includes(source));
+            }
+            pending.retain(|name| !closure.contains(name));
+            closure.insert(name);
+        }
+        closure
+    }
+
+    /// Whether a file under `templates/` is rendered into `build/` on its own. `page.html`, post
+    /// layouts, partials and templates other templates extend, include or import are not.
+    pub fn is_emitted(&self, path: &Path) -> bool {
+        path != Path::new(PAGE_TEMPLATE) && !self.shared.contains(&template_name(path))
     }
-    let mut buffer = String::new();
-    File::open(CONTENT_CACHE)
-        .unwrap()
-        .read_to_string(&mut buffer)
-        .unwrap();
-
-    let loaded_cache: Vec<CacheData> = serde_json::from_str(&buffer).unwrap();
-    static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{(.+?)\}\}").unwrap());
-
-    let template_file_as_string = change_file.to_string_lossy().to_string();
-    let template_file_content = fs::read_to_string(&template_file_as_string).unwrap();
-    let mut formatted_template = template_file_content.clone();
-
-    for mat in RE.captures_iter(&template_file_content) {
-        let tag_section = &mat[1];
-        let tags: Vec<&str> = tag_section.split_whitespace().collect();
-        let mut tags_matched_file: Vec<(PathBuf, String)> = Vec::new();
-        for tag in tags {
-            for file in &loaded_cache {
-                if file.tags.iter().any(|file_tag| file_tag == tag) {
-                    let html_file = html_file_name(&file.name.clone());
-                    tags_matched_file.push((html_file, file.title.clone()));
+
+    /// The files under `build/` that emitted templates are rendered to, including later pages.
+    pub fn template_outputs(&self) -> Vec<PathBuf> {
+        self.emitted_outputs().into_iter().map(|(output, _)| output).collect()
+    }
+
+    /// Every template output with the posts it can list: a term page's own posts, all posts
+    /// otherwise.
+    fn emitted_outputs(&self) -> Vec<(PathBuf, &[Value])> {
+        let mut outputs = Vec::new();
+        for name in self.tera.get_template_names() {
+            if !self.is_emitted(&Path::new(TEMPLATES_DIR).join(name)) {
+                continue;
+            }
+            let output = Path::new(BUILD_DIR).join(name);
+            let page_count = self.page_count(name, self.pages.len());
+            outputs.extend((1..=page_count).map(|number| (self.pagination_output(&output, number), self.pages.as_slice())));
+        }
+        for taxonomy in &self.taxonomies {
+            if self.tera.templates.contains_key(&taxonomy.term_template) {
+                for term in &taxonomy.terms {
+                    let page_count = self.page_count(&taxonomy.term_template, term.pages.len());
+                    outputs.extend(
+                        (1..=page_count).map(|number| (self.pagination_output(&term.output, number), term.pages.as_slice())),
+                    );
+                }
+            }
+            if self.tera.templates.contains_key(&taxonomy.index_template) {
+                outputs.push((taxonomy.index_output(), self.pages.as_slice()));
+            }
+        }
+        outputs
+    }
+
+    /// Write the search index and widget if `search` is enabled in `config.toml`.
+    pub fn render_search(&self) -> Result<()> {
+        if !self.settings.search {
+            return Ok(());
+        }
+        for output in search::write(&self.contents, &self.pages)? {
+            self.outputs.record(&output);
+        }
+        Ok(())
+    }
+
+    /// Write `sitemap.xml` listing every post and html template output, and `robots.txt` if
+    /// enabled and not a template of its own. Skipped without `base_url`, as sitemaps need absolute urls.
+    pub fn render_sitemap(&self) -> Result<()> {
+        let config = &self.settings;
+        if !config.sitemap.enabled {
+            return Ok(());
+        }
+        let Some(base_url) = config.base_url.as_deref() else {
+            if config.sitemap.robots {
+                logging::warn("`sitemap.xml` and `robots.txt` are skipped until `base_url` is set in `config.toml`.");
+            }
+            return Ok(());
+        };
+
+        let mut entries: Vec<(String, Option<&str>)> = self
+            .pages
+            .iter()
+            .map(|page| (page["url"].as_str().unwrap_or_default().to_string(), page["updated"].as_str()))
+            .collect();
+        for (output, pages) in self.emitted_outputs() {
+            if output.extension().is_some_and(|extension| extension == "html") {
+                entries.push((output_url(&output, self.pretty_urls), sitemap::newest(pages)));
+            }
+        }
+        // A `robots.txt` template takes precedence over the generated one.
+        let robots = config.sitemap.robots && !self.template_outputs().contains(&Path::new(BUILD_DIR).join("robots.txt"));
+        for output in sitemap::write(base_url, &entries, robots)? {
+            self.outputs.record(&output);
+        }
+        Ok(())
+    }
+
+    /// Write `index.json` if enabled, and the site's feeds, plus one per tag, from the newest posts.
+    /// Feeds are skipped without a `[feeds]` section in `config.toml`, and with a warning without
+    /// `base_url`.
+    pub fn render_feeds(&self) -> Result<()> {
+        let config = &self.settings;
+        if config.site_index {
+            self.outputs.record(&feeds::write_site_index(config, &self.contents, &self.pages)?);
+        }
+        if config.feeds.is_none() {
+            return Ok(());
+        }
+        let Some(writer) = FeedWriter::new(config, &self.contents) else {
+            logging::warn("feeds are skipped until `base_url` is set in `config.toml`.");
+            return Ok(());
+        };
+        let title = config.title.clone().unwrap_or_else(|| writer.absolute_url(""));
+        for output in writer.write(Path::new(""), &title, &self.pages)? {
+            self.outputs.record(&output);
+        }
+
+        if writer.settings().tags {
+            let tags = self.taxonomies.iter().find(|taxonomy| taxonomy.name == "tags");
+            for term in tags.into_iter().flat_map(|taxonomy| &taxonomy.terms) {
+                let directory = term.output.parent().unwrap_or(&term.output).strip_prefix(BUILD_DIR)?;
+                for output in writer.write(directory, &format!("{title} - {}", term.name), &term.pages)? {
+                    self.outputs.record(&output);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Render each taxonomy's term template once per term and its index template once, if the
+    /// templates exist (`tag.html` and `tags.html` for tags). A term page sees its term as `term`
+    /// and is paginated over the term's posts.
+    pub fn render_taxonomies(&self, mode: RenderMode) -> Result<()> {
+        for taxonomy in &self.taxonomies {
+            if self.tera.templates.contains_key(&taxonomy.term_template) {
+                for term in &taxonomy.terms {
+                    let mut context = Context::new();
+                    let mut variables = term.summary();
+                    variables["pages"] = term.pages.clone().into();
+                    variables["taxonomy"] = taxonomy.name.clone().into();
+                    context.insert("term", &variables);
+                    let outputs = self.render_template(&taxonomy.term_template, &term.output, &term.pages, &context)?;
+                    self.write_outputs(outputs, mode)?;
                 }
             }
+            if self.tera.templates.contains_key(&taxonomy.index_template) {
+                let mut context = Context::new();
+                context.insert("taxonomy", &taxonomy.name);
+                context.insert("terms", &taxonomy.terms.iter().map(Term::summary).collect::<Vec<_>>());
+                let outputs = self.render_template(&taxonomy.index_template, &taxonomy.index_output(), &self.pages, &context)?;
+                self.write_outputs(outputs, mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_outputs(&self, outputs: Vec<(PathBuf, String)>, mode: RenderMode) -> Result<()> {
+        for (output, rendered) in outputs {
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent).unwrap_or_else(|e| {
+                    logging::error(format!("could not create {}\n {}", parent.display(), e).as_str());
+                });
+            }
+            write(&output, finish_output(&output, rendered, mode))?;
+            self.outputs.record(&output);
+        }
+        Ok(())
+    }
+
+    /// How many pages a template is split into: one unless it uses `paginator`.
+    fn page_count(&self, name: &str, items: usize) -> usize {
+        match self.paginated.contains(name) {
+            true => items.div_ceil(self.settings.pagination.per_page.max(1)).max(1),
+            false => 1,
+        }
+    }
+
+    /// Where page `number` of a paginated template is written: the first page at the template's
+    /// own output, later ones under `page/<number>/` next to it.
+    fn pagination_output(&self, output: &Path, number: usize) -> PathBuf {
+        if number == 1 {
+            return output.to_path_buf();
         }
-        let replacement = li_href_generator(tags_matched_file);
-        formatted_template = formatted_template.replace(&mat[0], &replacement);
+        let directory = match output.file_name() {
+            Some(file_name) if file_name == "index.html" => output.parent().unwrap_or(output).to_path_buf(),
+            _ => output.with_extension(""),
+        };
+        directory
+            .join(&self.settings.pagination.path)
+            .join(number.to_string())
+            .join("index.html")
+    }
+
+    fn context(&self, output: &Path) -> Context {
+        let mut context = Context::new();
+        context.insert("config", &self.config);
+        context.insert("pages", &self.pages);
+        context.insert("tags", &self.tags);
+        context.insert("root", &root_prefix(output));
+        let taxonomies: serde_json::Map<String, Value> = self
+            .taxonomies
+            .iter()
+            .map(|taxonomy| (taxonomy.name.clone(), taxonomy.summaries().into()))
+            .collect();
+        context.insert("taxonomies", &taxonomies);
+        context
+    }
+
+    /// Render a post's html through its layout, `page.html` unless the post picks another.
+    pub fn render_page(&self, entry: &CacheData, content: &str, output: &Path) -> Result<String> {
+        let mut context = self.context(output);
+        context.insert("page", &entry.page_variables(self.pretty_urls));
+        context.insert("content", content);
+
+        let name = entry.layout_template();
+        if !self.tera.templates.contains_key(&name) {
+            bail!(
+                "`{}` uses the layout `{}`, but `{}{}` does not exist",
+                entry.name.display(),
+                entry.layout.as_deref().unwrap_or_default(),
+                TEMPLATES_DIR,
+                name
+            );
+        }
+        self.tera
+            .render(&name, &context)
+            .with_context(|| format!("failed to render `{}` with `{}`", entry.name.display(), name))
+    }
+
+    /// Render a template into each of its outputs: once, or once per page of `items` when it uses
+    /// `paginator`. `extra` is added to the usual context.
+    fn render_template(&self, name: &str, output: &Path, items: &[Value], extra: &Context) -> Result<Vec<(PathBuf, String)>> {
+        let page_count = self.page_count(name, items.len());
+        let per_page = self.settings.pagination.per_page.max(1);
+        let url = |number: usize| output_url(&self.pagination_output(output, number), self.pretty_urls);
+
+        let mut rendered = Vec::new();
+        for number in 1..=page_count {
+            let page_output = self.pagination_output(output, number);
+            let mut context = self.context(&page_output);
+            context.extend(extra.clone());
+            if self.paginated.contains(name) {
+                let start = (number - 1) * per_page;
+                context.insert("paginator", &serde_json::json!({
+                    "pages": &items[start.min(items.len())..(start + per_page).min(items.len())],
+                    "number": number,
+                    "total_pages": page_count,
+                    "total_items": items.len(),
+                    "per_page": per_page,
+                    "first": url(1),
+                    "last": url(page_count),
+                    "previous": (number > 1).then(|| url(number - 1)),
+                    "next": (number < page_count).then(|| url(number + 1)),
+                }));
+            }
+            let html = self
+                .tera
+                .render(name, &context)
+                .with_context(|| format!("failed to render template `{name}`"))?;
+            rendered.push((page_output, html));
+        }
+        Ok(rendered)
+    }
+}
+
+pub fn render_templates(templates_dir: &str, options: BuildOptions) -> Result<()> {
+    let cache = CacheData::read(CONTENT_CACHE)?;
+    let site = Site::load(&cache, options)?;
+    // Posts are written by `markdown_to_html_export` before this, or kept from an earlier build.
+    for entry in cache.iter().filter(|entry| options.publishes(entry)) {
+        site.outputs.record(&entry.output_file(site.pretty_urls));
+    }
+    for entry in WalkDir::new(templates_dir) {
+        template_engine(&site, &entry?.into_path(), options.mode)?;
+    }
+    site.render_taxonomies(options.mode)?;
+    site.render_feeds()?;
+    site.render_sitemap()?;
+    site.render_search()?;
+    // Only once everything rendered, so a failed build never removes the previous one's outputs.
+    site.outputs.replace(Outputs::read())
+}
+
+/// Render a single file under `templates/` into the matching path under `build/`.
+pub fn template_engine(site: &Site, change_file: &Path, mode: RenderMode) -> Result<()> {
+    if change_file.is_dir() || !site.is_emitted(change_file) {
+        return Ok(());
+    }
+    let outputs = site.render_template(&template_name(change_file), &template_output(change_file), &site.pages, &Context::new())?;
+    site.write_outputs(outputs, mode)
+}
+
+/// Minify rendered html and apply the render mode. Other outputs (xml, txt, ...) are left as-is.
+pub fn finish_output(output: &Path, mut rendered: String, mode: RenderMode) -> String {
+    let is_html = output
+        .extension()
+        .is_some_and(|ext| ext == "html" || ext == "htm");
+    if !is_html {
+        return rendered;
     }
     let minification_config = minify_html_onepass::Cfg::new();
-    let minified_template = minify_html_onepass::in_place_str(&mut formatted_template, &minification_config).unwrap();
-    write(&template_file_as_string.replace("templates/", "build/"), &minified_template).unwrap();
+    match minify_html_onepass::in_place_str(&mut rendered, &minification_config) {
+        Ok(minified) => mode.finish_html(minified),
+        Err(_) => {
+            logging::warn(format!("could not minify {}; writing it as-is.", output.display()).as_str());
+            mode.finish_html(&rendered)
+        }
+    }
+}
+
+/// Where a standalone template under `templates/` is written, or its first page if paginated.
+pub fn template_output(path: &Path) -> PathBuf {
+    Path::new(BUILD_DIR).join(path.strip_prefix(TEMPLATES_DIR).unwrap_or(path))
+}
+
+/// The name a template is registered under in Tera: its path relative to `templates/`.
+fn template_name(path: &Path) -> String {
+    path.strip_prefix(TEMPLATES_DIR)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// The relative path from an output file back to the root of `build/`, e.g. `../` for pages.
+fn root_prefix(output: &Path) -> String {
+    let depth = output
+        .strip_prefix(BUILD_DIR)
+        .map(|relative| relative.components().count().saturating_sub(1))
+        .unwrap_or(0);
+    "../".repeat(depth)
 }
 
-fn li_href_generator(meta_data: Vec<(PathBuf, String)>) -> String {
-    let config_file = fs::read_to_string("config.toml").unwrap();
-    let config: Config = toml::from_str(&config_file).unwrap();
-    let sorting = config.tagging.sorted;
+/// Templates under a `partials/` directory or starting with `_` are never emitted, even when
+/// nothing includes them (yet).
+fn is_partial(name: &str) -> bool {
+    name.split('/').any(|part| part == "partials" || part.starts_with('_'))
+}
+
+/// Templates that render `{{ content }}` only make sense as a post's layout, whether or not a post
+/// currently uses them.
+fn page_layouts(templates: &[(String, String)]) -> Vec<String> {
+    static CONTENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{-?\s*content\b").unwrap());
+
+    templates
+        .iter()
+        .filter(|(_, source)| CONTENT.is_match(source))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Templates that use the `paginator` variable and are therefore split into pages.
+fn paginated_templates(templates: &[(String, String)]) -> HashSet<String> {
+    static PAGINATOR: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bpaginator\b").unwrap());
+
+    templates
+        .iter()
+        .filter(|(_, source)| PAGINATOR.is_match(source))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Every template named by an `{% include %}` tag, including each choice of `include [...]`.
+fn included_templates(templates: &[(String, String)]) -> HashSet<String> {
+    templates.iter().flat_map(|(_, source)| template_includes(source)).collect()
+}
+
+/// The templates one template includes.
+fn template_includes(source: &str) -> Vec<String> {
+    static INCLUDE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\{%-?\s*include\s+(\[[^\]]*\]|"[^"]*"|'[^']*')"#).unwrap());
+    static NAME: Lazy<Regex> = Lazy::new(|| Regex::new(r#""([^"]*)"|'([^']*)'"#).unwrap());
 
-    let mut unique_items = HashSet::new();
-    let mut deduplicated_data = Vec::new();
-    for item in meta_data {
-        if unique_items.insert(item.1.clone()) {
-            deduplicated_data.push(item);
-            if sorting {
-                deduplicated_data.sort();
+    let mut included = Vec::new();
+    for include in INCLUDE.captures_iter(source) {
+        for name in NAME.captures_iter(&include[1]) {
+            let name = name.get(1).or_else(|| name.get(2)).unwrap();
+            included.push(name.as_str().to_string());
+        }
+    }
+    included
+}
+
+fn read_templates(templates_dir: &str) -> Result<Vec<(String, String)>> {
+    let mut templates = Vec::new();
+    for entry in WalkDir::new(templates_dir) {
+        let path: PathBuf = entry?.into_path();
+        if path.is_file() {
+            let source = fs::read_to_string(&path)
+                .with_context(|| format!("could not read template `{}`", path.display()))?;
+            templates.push((template_name(&path), upgrade_legacy_placeholders(&source)));
+        }
+    }
+    Ok(templates)
+}
+
+/// Turn listing placeholders from before templates were rendered with Tera (`{{ rust wasm }}`)
+/// into calls to the `listing` function, and a bare `{{ content }}` into `{{ content | safe }}`,
+/// so existing templates keep working with autoescaping.
+///
+/// Only bare words (and the parentheses of a tag query like `{{ rust AND NOT (wasm OR web) }}`)
+/// are rewritten, and never expressions using context or loop variables.
+fn upgrade_legacy_placeholders(source: &str) -> String {
+    static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\{\{-?\s*((?:\(*[\w-]+\)*|[()])(?:\s+(?:\(*[\w-]+\)*|[()]))*)\s*-?\}\}").unwrap()
+    });
+    static CONTENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{(-?)\s*content\s*(-?)\}\}").unwrap());
+    static DECLARATION: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\{%-?\s*(?:for\s+(\w+)(?:\s*,\s*(\w+))?\s+in|set(?:_global)?\s+(\w+)\s*=|macro\s+\w+\s*\(([^)]*)\))").unwrap()
+    });
+
+    let source = CONTENT.replace_all(source, "{{$1 content | safe $2}}");
+    let mut declared: HashSet<&str> = CONTEXT_VARIABLES.into_iter().collect();
+    declared.insert("loop");
+    for caps in DECLARATION.captures_iter(&source) {
+        for group in [1, 2, 3] {
+            if let Some(name) = caps.get(group) {
+                declared.insert(name.as_str());
             }
         }
+        if let Some(arguments) = caps.get(4) {
+            declared.extend(arguments.as_str().split(',').filter_map(|arg| arg.split('=').next()).map(str::trim));
+        }
     }
-    let mut container = String::from("<ul>\n");
-    let output: String = deduplicated_data
-        .iter()
-        .map(|item| format!("<li><a href=\"page/{}\">{}</a></li>", item.0.to_string_lossy().to_string(), item.1))
-        .collect::<Vec<String>>()
-        .join("\n");
-    container.push_str(&output);
-    container.push_str("\n</ul>");
 
-    return container;
+    PLACEHOLDER
+        .replace_all(&source, |caps: &regex::Captures| {
+            let words = &caps[1];
+            let mut names = words.split(|c: char| c.is_whitespace() || c == '(' || c == ')');
+            if names.any(|name| declared.contains(name)) {
+                return caps[0].to_string();
+            }
+            format!("{{{{ listing(tags=\"{words}\", root=root) }}}}")
+        })
+        .into_owned()
+}
+
+/// The terms of a page field: a list, or a single string (whitespace separated for `tags`, which
+/// the front matter already turned into a list).
+fn page_terms(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(term) if term.trim().is_empty() => vec![],
+        Value::String(term) => vec![term.trim().to_string()],
+        other => terms_from_value(other),
+    }
+}
+
+/// Order pages by one of their fields. Posts missing the field always come last, and ties keep
+/// source path order so the result does not depend on the order of the cache.
+fn sort_pages(pages: &mut [Value], key: &str, order: SortOrder) {
+    pages.sort_by(|a, b| {
+        let ordering = match (&a[key], &b[key]) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            (a_value, b_value) => {
+                let ordering = compare_values(key, a_value, b_value);
+                match order {
+                    SortOrder::Asc => ordering,
+                    SortOrder::Desc => ordering.reverse(),
+                }
+            }
+        };
+        ordering.then_with(|| a["path"].as_str().cmp(&b["path"].as_str()))
+    });
+}
+
+fn compare_values(key: &str, a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+        // Dates carry their own offsets, so their text does not sort chronologically.
+        (Value::String(a), Value::String(b)) if key == "date" => parse_date(a).cmp(&parse_date(b)),
+        (Value::String(a), Value::String(b)) => a.to_lowercase().cmp(&b.to_lowercase()).then_with(|| a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (a, b) => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+/// A Tera instance escaping every value in `.html` templates unless it is marked `| safe`.
+/// `| escape` counts as safe too, so templates written before autoescaping do not escape twice.
+fn escaping_tera() -> Tera {
+    let mut tera = Tera::default();
+    tera.register_filter("escape", Escape);
+    tera
+}
+
+/// Tera's `escape` filter, with its output marked safe from autoescaping.
+struct Escape;
+
+impl tera::Filter for Escape {
+    fn filter(&self, value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+        match value {
+            Value::String(text) => Ok(Value::String(tera::escape_html(text))),
+            other => Ok(Value::String(tera::escape_html(&other.to_string()))),
+        }
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `listing(tags="rust wasm")`: a `<ul>` of every post carrying any of the tags, each rendered
+/// with the `item` markup from `[tagging]` in `config.toml`. `sort_by="title"` and `order="desc"`
+/// override the site-wide order for one listing.
+struct Listing {
+    pages: Vec<Value>,
+    /// The `item` markup, as the template `item.html`.
+    item: Tera,
+    /// The site-wide sort key, used when a listing only overrides the order.
+    sort_by: Option<String>,
+    /// Each taxonomy's name with the page field holding its terms.
+    fields: Vec<(String, String)>,
+}
+
+impl tera::Function for Listing {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        // Each taxonomy argument selects the posts with any of its terms (or matching the query,
+        // for tags); all of them must match.
+        let mut filters: Vec<(&str, Filter)> = Vec::new();
+        let mut first = match args.get("first") {
+            None => None,
+            Some(first) => Some(
+                first
+                    .as_u64()
+                    .ok_or_else(|| tera::Error::msg("`listing` expects `first` to be a number of posts"))? as usize,
+            ),
+        };
+        for (name, field) in &self.fields {
+            let filter = match args.get(name) {
+                None => continue,
+                Some(Value::String(query)) if name == "tags" => {
+                    let query = TagQuery::parse(query).map_err(|err| tera::Error::msg(err.to_string()))?;
+                    first = first.or(query.first);
+                    query.filter
+                }
+                Some(terms) => Filter::any_of(page_terms(terms))
+                    .ok_or_else(|| tera::Error::msg(format!("`listing` got no terms for `{name}`")))?,
+            };
+            filters.push((field, filter));
+        }
+        if filters.is_empty() {
+            return Err("`listing` expects the terms to list, e.g. listing(tags=\"rust AND NOT wasm\")".into());
+        }
+        let root = args.get("root").and_then(Value::as_str).unwrap_or("");
+
+        let sort_by = match args.get("sort_by") {
+            None => None,
+            Some(Value::String(key)) => Some(key.as_str()),
+            Some(_) => return Err("`listing` expects `sort_by` to be a field name, e.g. sort_by=\"date\"".into()),
+        };
+        let order: Option<SortOrder> = match args.get("order") {
+            None => None,
+            Some(order) => Some(
+                serde_json::from_value(order.clone())
+                    .map_err(|_| tera::Error::msg("`listing` expects `order` to be \"asc\" or \"desc\""))?,
+            ),
+        };
+        let mut pages = Cow::Borrowed(&self.pages);
+        if sort_by.is_some() || order.is_some() {
+            let key = sort_by.or(self.sort_by.as_deref()).unwrap_or("path");
+            sort_pages(pages.to_mut(), key, order.unwrap_or_default());
+        }
+
+        let mut items = Vec::new();
+        let matching = pages.iter().filter(|page| {
+            filters.iter().all(|(field, filter)| filter.matches(&page_terms(&page[*field])))
+        });
+        for page in matching.take(first.unwrap_or(usize::MAX)) {
+            let mut context = Context::new();
+            context.insert("page", page);
+            context.insert("root", root);
+            items.push(self.item.render("item.html", &context)?);
+        }
+
+        Ok(Value::String(format!("<ul>\n{}\n</ul>", items.join("\n"))))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrades_bare_word_placeholders_to_listings() {
+        assert_eq!(
+            upgrade_legacy_placeholders("<ul>{{ rust wasm }}</ul>"),
+            "<ul>{{ listing(tags=\"rust wasm\", root=root) }}</ul>"
+        );
+        assert_eq!(
+            upgrade_legacy_placeholders("{{ rust AND NOT (wasm OR web) }}"),
+            "{{ listing(tags=\"rust AND NOT (wasm OR web)\", root=root) }}"
+        );
+    }
+
+    #[test]
+    fn keeps_variables_declared_by_the_template() {
+        let source = "{% for x in pages %}{{ x }}{% endfor %}";
+        assert_eq!(upgrade_legacy_placeholders(source), source);
+
+        let source = "{% set title = page.title %}{{ title }}{% macro item(post, depth=1) %}{{ post }}{{ depth }}{% endmacro %}";
+        assert_eq!(upgrade_legacy_placeholders(source), source);
+    }
+
+    #[test]
+    fn keeps_expressions_and_context_variables() {
+        for source in ["{{ page.title }}", "{{ root }}", "{{ title | upper }}", "{{ loop }}", "{{ paginator }}"] {
+            assert_eq!(upgrade_legacy_placeholders(source), source);
+        }
+    }
+
+    #[test]
+    fn marks_bare_content_safe() {
+        assert_eq!(upgrade_legacy_placeholders("<main>{{content}}</main>"), "<main>{{ content | safe }}</main>");
+        assert_eq!(upgrade_legacy_placeholders("{{- content -}}"), "{{- content | safe -}}");
+        assert_eq!(upgrade_legacy_placeholders("{{ content | safe }}"), "{{ content | safe }}");
+    }
 }
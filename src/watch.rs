@@ -0,0 +1,150 @@
+use crate::{cache::CacheData, logging, outputs::Outputs, server, source::{self, BuildOptions}, templates, ASSETS, CONFIG, CONTENT, CONTENT_CACHE, TEMPLATES_DIR};
+use anyhow::Result;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{
+    collections::BTreeSet,
+    env,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+/// How long to wait for more events after the first one before rebuilding. Editors often write a
+/// file in several steps (truncate, write, rename), which we would otherwise rebuild for each time.
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+#[derive(Default)]
+struct Changes {
+    config: bool,
+    content: bool,
+    templates: BTreeSet<PathBuf>,
+    assets: BTreeSet<PathBuf>,
+}
+
+/// Watch `content/`, `templates/`, `assets/` and `config.toml`, rebuilding only the stages
+/// affected by each change. Blocks forever; failed rebuilds are logged and watching continues.
+pub fn watch(options: BuildOptions) -> Result<()> {
+    let root = env::current_dir()?.canonicalize()?;
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+
+    for directory in [CONTENT, TEMPLATES_DIR, ASSETS] {
+        if Path::new(directory).is_dir() {
+            watcher.watch(Path::new(directory), RecursiveMode::Recursive)?;
+        }
+    }
+    // Watch the project root rather than `config.toml` itself: editors that save by renaming a
+    // temporary file over the original would otherwise leave us watching a deleted inode.
+    watcher.watch(Path::new("."), RecursiveMode::NonRecursive)?;
+    logging::info("watching for changes...");
+
+    loop {
+        let mut changes = Changes::default();
+        let mut event = receiver.recv()?;
+        loop {
+            match event {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    for path in event.paths {
+                        let relative = path
+                            .strip_prefix(&root)
+                            .or_else(|_| path.strip_prefix("."))
+                            .unwrap_or(&path);
+                        changes.record(relative);
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => logging::warn(format!("watch error: {err}").as_str()),
+            }
+            event = match receiver.recv_timeout(DEBOUNCE) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+        }
+
+        match rebuild(&changes, options) {
+            Ok(true) => server::notify_reload(),
+            Ok(false) => {}
+            Err(err) => logging::error(format!("rebuild failed: {err:#}").as_str()),
+        }
+    }
+}
+
+impl Changes {
+    fn record(&mut self, path: &Path) {
+        if path == Path::new(CONFIG) {
+            self.config = true;
+        } else if path.starts_with(CONTENT) {
+            self.content |= path.extension().is_some_and(|ext| ext == "md");
+        } else if path.starts_with(TEMPLATES_DIR) {
+            self.templates.insert(path.to_path_buf());
+        } else if path.starts_with(ASSETS) {
+            self.assets.insert(path.to_path_buf());
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.config && !self.content && self.templates.is_empty() && self.assets.is_empty()
+    }
+}
+
+/// Rebuild what `changes` affect, returning whether anything was rebuilt at all; events for files
+/// migi does not use (editor swap or backup files next to `config.toml`) change nothing.
+fn rebuild(changes: &Changes, options: BuildOptions) -> Result<bool> {
+    if changes.is_empty() {
+        return Ok(false);
+    }
+    source::prechecks()?;
+
+    let site = match changes.templates.is_empty() {
+        true => None,
+        false => Some(templates::Site::load(&CacheData::read(CONTENT_CACHE)?, options)?),
+    };
+
+    // Pages are rendered through `page.html` (and whatever it extends or includes) and
+    // highlighted with the theme from the config; `markdown_to_html_export` re-renders the ones
+    // depending on whatever changed.
+    let shared_template_changed = site
+        .as_ref()
+        .is_some_and(|site| changes.templates.iter().any(|path| !site.is_emitted(path)));
+    let theme_changed = changes.assets.iter().any(|path| path.starts_with(Path::new(ASSETS).join("syntax")));
+    // Adding or deleting a template changes which files are written, which only a full render
+    // records in `cache/outputs.json` (removing a deleted template's output on the way).
+    let outputs_changed = site.as_ref().is_some_and(|site| {
+        let previous = Outputs::read();
+        changes.templates.iter().any(|path| {
+            !path.exists()
+                || (path.is_file() && site.is_emitted(path) && !previous.contains(&templates::template_output(path)))
+        })
+    });
+
+    if changes.content || changes.config || shared_template_changed || theme_changed {
+        let content_cache = source::scan_cache()?;
+        if changes.config && source::build_stamp_changed(options)? {
+            content_cache.mark_all_changed();
+        }
+        let work_count = source::markdown_to_html_export(content_cache, options)?;
+        logging::info(format!("{} files were affected.", work_count).as_str());
+
+        // Listings are generated from the content cache, and shared templates are part of every
+        // other template, so all of them have to be re-rendered.
+        templates::render_templates(TEMPLATES_DIR, options)?;
+    } else if outputs_changed {
+        templates::render_templates(TEMPLATES_DIR, options)?;
+    } else if let Some(site) = &site {
+        for template in changes.templates.iter().filter(|path| path.is_file()) {
+            logging::info(format!("re-rendering {}", template.display()).as_str());
+            templates::template_engine(site, template, options.mode)?;
+        }
+    }
+
+    if changes.config {
+        source::copy_assets(ASSETS)?;
+    } else {
+        for asset in changes.assets.iter().filter(|path| path.exists()) {
+            logging::info(format!("copying {}", asset.display()).as_str());
+            source::copy_assets(&asset.to_string_lossy())?;
+        }
+    }
+    Ok(true)
+}
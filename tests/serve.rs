@@ -0,0 +1,94 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const MIGI: &str = env!("CARGO_BIN_EXE_migi");
+const RELOAD_SCRIPT: &str = "/__migi/reload";
+
+/// A fresh project from `migi new` in the temp directory.
+fn new_project(name: &str) -> PathBuf {
+    let project = env::temp_dir().join(format!("migi-test-{}-{name}", std::process::id()));
+    if project.exists() {
+        fs::remove_dir_all(&project).unwrap();
+    }
+    // `migi new` exits with an error status even when it succeeds.
+    migi(&env::temp_dir(), &["new", project.to_str().unwrap()]);
+    assert!(project.join("config.toml").is_file());
+    project
+}
+
+fn migi(directory: &Path, arguments: &[&str]) -> Output {
+    Command::new(MIGI).args(arguments).current_dir(directory).output().unwrap()
+}
+
+/// Every html file under `build/` that contains the live reload script.
+fn pages_with_reload_script(project: &Path) -> Vec<PathBuf> {
+    let mut pages = Vec::new();
+    let mut directories = vec![project.join("build")];
+    while let Some(directory) = directories.pop() {
+        let Ok(entries) = fs::read_dir(directory) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                directories.push(path);
+            } else if path.extension().is_some_and(|extension| extension == "html")
+                && fs::read_to_string(&path).unwrap().contains(RELOAD_SCRIPT)
+            {
+                pages.push(path);
+            }
+        }
+    }
+    pages
+}
+
+/// Stops `migi serve` even when the test fails.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Run `migi serve` until its first build has written the reload script into `page`.
+fn serve_until_built(project: &Path, page: &Path) {
+    let _server = Server(
+        Command::new(MIGI)
+            .args(["serve", "--port", "0"])
+            .current_dir(project)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let started = Instant::now();
+    while !pages_with_reload_script(project).iter().any(|path| path == page) {
+        assert!(started.elapsed() < Duration::from_secs(30), "`migi serve` never built {}", page.display());
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn build_after_serve_and_a_failed_build_drops_the_reload_script() {
+    let project = new_project("serve-then-failed-build");
+    fs::write(project.join("content/about.md"), "---\ntitle: About\npermalink: /about/\n---\nAbout me.\n").unwrap();
+    serve_until_built(&project, &project.join("build/about/index.html"));
+
+    let clash = project.join("content/clash.md");
+    fs::write(&clash, "---\ntitle: Clash\npermalink: /about/\n---\nSame place.\n").unwrap();
+    let failed = migi(&project, &["build"]);
+    assert!(!failed.status.success());
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("would both be written to"));
+
+    fs::remove_file(clash).unwrap();
+    assert!(migi(&project, &["build"]).status.success());
+    assert_eq!(pages_with_reload_script(&project), Vec::<PathBuf>::new());
+    fs::remove_dir_all(project).unwrap();
+}